@@ -0,0 +1,5 @@
+CREATE TABLE todos (
+    id SERIAL PRIMARY KEY,
+    description TEXT NOT NULL,
+    completed BOOLEAN NOT NULL DEFAULT FALSE
+);
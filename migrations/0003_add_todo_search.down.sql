@@ -0,0 +1,4 @@
+DROP TRIGGER todos_search_vector_update ON todos;
+DROP FUNCTION todos_search_vector_update();
+ALTER TABLE todos DROP COLUMN search_vector;
+DROP TABLE search_settings;
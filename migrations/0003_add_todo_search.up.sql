@@ -0,0 +1,27 @@
+-- The text search configuration used to build and query search vectors.
+-- The server keeps it in sync with its `search.language` setting.
+CREATE TABLE search_settings (
+    id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+    language REGCONFIG NOT NULL
+);
+
+INSERT INTO search_settings (language) VALUES ('english');
+
+ALTER TABLE todos ADD COLUMN search_vector TSVECTOR;
+
+CREATE FUNCTION todos_search_vector_update() RETURNS trigger AS $$
+BEGIN
+    NEW.search_vector := to_tsvector((SELECT language FROM search_settings), NEW.description);
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER todos_search_vector_update
+    BEFORE INSERT OR UPDATE OF description ON todos
+    FOR EACH ROW EXECUTE FUNCTION todos_search_vector_update();
+
+UPDATE todos SET search_vector = to_tsvector('english', description);
+
+ALTER TABLE todos ALTER COLUMN search_vector SET NOT NULL;
+
+CREATE INDEX todos_search_vector_idx ON todos USING GIN (search_vector);
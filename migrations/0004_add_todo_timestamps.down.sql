@@ -0,0 +1,6 @@
+DROP TRIGGER todos_timestamps_update ON todos;
+DROP FUNCTION todos_timestamps_update();
+ALTER TABLE todos
+    DROP CONSTRAINT todos_completed_at_check,
+    DROP COLUMN completed_at,
+    DROP COLUMN updated_at;
@@ -0,0 +1,43 @@
+ALTER TABLE todos
+    ADD COLUMN updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    ADD COLUMN completed_at TIMESTAMPTZ;
+
+-- The real times are unknown for existing rows, creation time is the best
+-- approximation.
+UPDATE todos
+SET updated_at = created_at,
+    completed_at = CASE WHEN completed THEN created_at END;
+
+ALTER TABLE todos ADD CONSTRAINT todos_completed_at_check
+    CHECK (completed = (completed_at IS NOT NULL));
+
+-- Bump `updated_at` whenever a row actually changes, and set or clear
+-- `completed_at` as `completed` flips.
+CREATE FUNCTION todos_timestamps_update() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP = 'UPDATE' THEN
+        IF to_jsonb(NEW) - 'search_vector' - 'updated_at'
+            IS DISTINCT FROM to_jsonb(OLD) - 'search_vector' - 'updated_at' THEN
+            NEW.updated_at := now();
+        END IF;
+    END IF;
+
+    IF NOT NEW.completed THEN
+        NEW.completed_at := NULL;
+    ELSIF TG_OP = 'INSERT' THEN
+        NEW.completed_at := COALESCE(NEW.completed_at, now());
+    ELSIF NOT OLD.completed THEN
+        NEW.completed_at := now();
+    ELSE
+        NEW.completed_at := OLD.completed_at;
+    END IF;
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER todos_timestamps_update
+    BEFORE INSERT OR UPDATE ON todos
+    FOR EACH ROW EXECUTE FUNCTION todos_timestamps_update();
+
+CREATE INDEX todos_updated_at_idx ON todos (updated_at, id);
+CREATE INDEX todos_completed_at_idx ON todos ((COALESCE(completed_at, 'infinity')), id);
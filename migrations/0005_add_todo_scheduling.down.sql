@@ -0,0 +1,5 @@
+ALTER TABLE todos
+    DROP CONSTRAINT todos_start_on_check,
+    DROP COLUMN priority,
+    DROP COLUMN start_on,
+    DROP COLUMN due_at;
@@ -0,0 +1,12 @@
+ALTER TABLE todos
+    ADD COLUMN due_at TIMESTAMPTZ,
+    ADD COLUMN start_on DATE,
+    -- 0 low, 1 normal, 2 high, 3 urgent
+    ADD COLUMN priority SMALLINT NOT NULL DEFAULT 1
+        CONSTRAINT todos_priority_check CHECK (priority BETWEEN 0 AND 3),
+    ADD CONSTRAINT todos_start_on_check
+        CHECK (start_on <= (due_at AT TIME ZONE 'UTC')::date);
+
+CREATE INDEX todos_due_at_idx ON todos ((COALESCE(due_at, 'infinity')), id);
+CREATE INDEX todos_start_on_idx ON todos ((COALESCE(start_on, 'infinity')), id);
+CREATE INDEX todos_priority_idx ON todos (priority, id);
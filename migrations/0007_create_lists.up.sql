@@ -0,0 +1,24 @@
+CREATE TABLE lists (
+    id SERIAL PRIMARY KEY,
+    name TEXT NOT NULL,
+    description TEXT,
+    -- Lists are shown in ascending position, ties broken by id.
+    position INTEGER NOT NULL DEFAULT 0,
+    archived BOOLEAN NOT NULL DEFAULT false,
+    -- The inbox receives todos created without a list.
+    inbox BOOLEAN NOT NULL DEFAULT false,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    CONSTRAINT lists_inbox_archived_check CHECK (NOT (inbox AND archived))
+);
+
+CREATE UNIQUE INDEX lists_inbox_idx ON lists ((true)) WHERE inbox;
+CREATE INDEX lists_position_idx ON lists (position, id);
+
+INSERT INTO lists (name, position, inbox) VALUES ('Inbox', 0, true);
+
+ALTER TABLE todos ADD COLUMN list_id INTEGER REFERENCES lists (id) ON DELETE CASCADE;
+UPDATE todos SET list_id = (SELECT id FROM lists WHERE inbox);
+ALTER TABLE todos ALTER COLUMN list_id SET NOT NULL;
+
+CREATE INDEX todos_list_id_idx ON todos (list_id, id);
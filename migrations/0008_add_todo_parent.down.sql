@@ -0,0 +1,5 @@
+ALTER TABLE todos
+    DROP CONSTRAINT todos_parent_id_check,
+    DROP CONSTRAINT todos_parent_fkey,
+    DROP COLUMN parent_id,
+    DROP CONSTRAINT todos_id_list_id_key;
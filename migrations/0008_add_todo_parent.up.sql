@@ -0,0 +1,10 @@
+-- Subtasks live in the same list as their parent. The composite key moves a
+-- whole subtree along when its root changes list.
+ALTER TABLE todos
+    ADD CONSTRAINT todos_id_list_id_key UNIQUE (id, list_id),
+    ADD COLUMN parent_id INTEGER,
+    ADD CONSTRAINT todos_parent_fkey FOREIGN KEY (parent_id, list_id)
+        REFERENCES todos (id, list_id) ON UPDATE CASCADE ON DELETE CASCADE,
+    ADD CONSTRAINT todos_parent_id_check CHECK (parent_id <> id);
+
+CREATE INDEX todos_parent_id_idx ON todos (parent_id, id);
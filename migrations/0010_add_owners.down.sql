@@ -0,0 +1,31 @@
+DROP POLICY todo_tags_owner ON todo_tags;
+ALTER TABLE todo_tags NO FORCE ROW LEVEL SECURITY, DISABLE ROW LEVEL SECURITY;
+DROP POLICY tags_owner ON tags;
+ALTER TABLE tags NO FORCE ROW LEVEL SECURITY, DISABLE ROW LEVEL SECURITY;
+DROP POLICY todos_owner ON todos;
+ALTER TABLE todos NO FORCE ROW LEVEL SECURITY, DISABLE ROW LEVEL SECURITY;
+DROP POLICY lists_owner ON lists;
+ALTER TABLE lists NO FORCE ROW LEVEL SECURITY, DISABLE ROW LEVEL SECURITY;
+
+DROP FUNCTION app_maintenance();
+DROP FUNCTION app_user_id();
+
+DROP INDEX tags_name_idx;
+ALTER TABLE tags DROP COLUMN owner_id;
+CREATE UNIQUE INDEX tags_name_idx ON tags (lower(name));
+
+DROP INDEX todos_owner_id_idx;
+ALTER TABLE todos
+    DROP CONSTRAINT todos_list_fkey,
+    ADD CONSTRAINT todos_list_id_fkey FOREIGN KEY (list_id)
+        REFERENCES lists (id) ON DELETE CASCADE;
+ALTER TABLE todos DROP COLUMN owner_id;
+
+-- Only one inbox can remain, the others become ordinary lists.
+UPDATE lists SET inbox = false
+WHERE inbox AND owner_id <> (SELECT min(owner_id) FROM lists WHERE inbox);
+DROP INDEX lists_position_idx;
+DROP INDEX lists_inbox_idx;
+ALTER TABLE lists DROP CONSTRAINT lists_id_owner_id_key, DROP COLUMN owner_id;
+CREATE UNIQUE INDEX lists_inbox_idx ON lists ((true)) WHERE inbox;
+CREATE INDEX lists_position_idx ON lists (position, id);
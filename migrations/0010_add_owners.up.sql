@@ -0,0 +1,72 @@
+-- Every list, todo and tag now belongs to a user. Rows that predate user
+-- accounts are handed to the earliest registered user.
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM users)
+        AND (EXISTS (SELECT 1 FROM todos) OR EXISTS (SELECT 1 FROM tags)) THEN
+        RAISE EXCEPTION 'register a user to take over the existing todos before applying this migration';
+    END IF;
+END
+$$;
+
+ALTER TABLE lists ADD COLUMN owner_id INTEGER REFERENCES users (id) ON DELETE CASCADE;
+ALTER TABLE todos ADD COLUMN owner_id INTEGER;
+ALTER TABLE tags ADD COLUMN owner_id INTEGER REFERENCES users (id) ON DELETE CASCADE;
+
+UPDATE lists SET owner_id = (SELECT min(id) FROM users);
+-- Without any users the shared inbox is empty; every user gets their own.
+DELETE FROM lists WHERE owner_id IS NULL;
+INSERT INTO lists (owner_id, name, position, inbox)
+SELECT id, 'Inbox', 0, true FROM users WHERE id <> (SELECT min(id) FROM users);
+
+ALTER TABLE todos DISABLE TRIGGER todos_timestamps_update;
+UPDATE todos SET owner_id = (SELECT min(id) FROM users);
+ALTER TABLE todos ENABLE TRIGGER todos_timestamps_update;
+UPDATE tags SET owner_id = (SELECT min(id) FROM users);
+
+ALTER TABLE lists
+    ALTER COLUMN owner_id SET NOT NULL,
+    ADD CONSTRAINT lists_id_owner_id_key UNIQUE (id, owner_id);
+DROP INDEX lists_inbox_idx;
+CREATE UNIQUE INDEX lists_inbox_idx ON lists (owner_id) WHERE inbox;
+DROP INDEX lists_position_idx;
+CREATE INDEX lists_position_idx ON lists (owner_id, position, id);
+
+-- A todo always belongs to the owner of its list.
+ALTER TABLE todos
+    ALTER COLUMN owner_id SET NOT NULL,
+    DROP CONSTRAINT todos_list_id_fkey,
+    ADD CONSTRAINT todos_list_fkey FOREIGN KEY (list_id, owner_id)
+        REFERENCES lists (id, owner_id) ON DELETE CASCADE;
+CREATE INDEX todos_owner_id_idx ON todos (owner_id, id);
+
+ALTER TABLE tags ALTER COLUMN owner_id SET NOT NULL;
+DROP INDEX tags_name_idx;
+CREATE UNIQUE INDEX tags_name_idx ON tags (owner_id, lower(name));
+
+-- Row level security backs up the ownership checks in the handlers. The
+-- server sets `app.user_id` in every transaction made on behalf of a user;
+-- maintenance tasks spanning all users set `app.maintenance` instead.
+CREATE FUNCTION app_user_id() RETURNS INTEGER AS $$
+    SELECT NULLIF(current_setting('app.user_id', true), '')::integer
+$$ LANGUAGE sql STABLE;
+
+CREATE FUNCTION app_maintenance() RETURNS BOOLEAN AS $$
+    SELECT COALESCE(current_setting('app.maintenance', true) = 'on', false)
+$$ LANGUAGE sql STABLE;
+
+ALTER TABLE lists ENABLE ROW LEVEL SECURITY, FORCE ROW LEVEL SECURITY;
+CREATE POLICY lists_owner ON lists
+    USING (owner_id = app_user_id() OR app_maintenance());
+
+ALTER TABLE todos ENABLE ROW LEVEL SECURITY, FORCE ROW LEVEL SECURITY;
+CREATE POLICY todos_owner ON todos
+    USING (owner_id = app_user_id() OR app_maintenance());
+
+ALTER TABLE tags ENABLE ROW LEVEL SECURITY, FORCE ROW LEVEL SECURITY;
+CREATE POLICY tags_owner ON tags
+    USING (owner_id = app_user_id() OR app_maintenance());
+
+ALTER TABLE todo_tags ENABLE ROW LEVEL SECURITY, FORCE ROW LEVEL SECURITY;
+CREATE POLICY todo_tags_owner ON todo_tags
+    USING (EXISTS (SELECT 1 FROM todos WHERE todos.id = todo_tags.todo_id));
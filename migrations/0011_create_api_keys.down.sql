@@ -0,0 +1,7 @@
+ALTER POLICY todos_owner ON todos
+    USING (owner_id = app_user_id() OR app_maintenance());
+ALTER POLICY lists_owner ON lists
+    USING (owner_id = app_user_id() OR app_maintenance());
+
+DROP FUNCTION app_list_allowed(INTEGER);
+DROP TABLE api_keys;
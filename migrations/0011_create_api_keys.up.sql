@@ -0,0 +1,31 @@
+CREATE TABLE api_keys (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    -- The start of the key, to tell keys apart without storing them.
+    prefix TEXT NOT NULL,
+    key_hash BYTEA NOT NULL UNIQUE,
+    scopes TEXT[] NOT NULL CONSTRAINT api_keys_scopes_check
+        CHECK (scopes <@ ARRAY['todos:read', 'todos:write', 'admin']),
+    -- When set, the key only reaches todos in these lists.
+    list_ids INTEGER[],
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_used_at TIMESTAMPTZ
+);
+
+CREATE INDEX api_keys_user_id_idx ON api_keys (user_id, id);
+
+-- Transactions made with an API key also set `app.api_key_id`, which
+-- confines them to the lists the key is restricted to.
+CREATE FUNCTION app_list_allowed(list_id INTEGER) RETURNS BOOLEAN AS $$
+    SELECT COALESCE(
+        (SELECT list_id = ANY(k.list_ids) FROM api_keys k
+        WHERE k.id = NULLIF(current_setting('app.api_key_id', true), '')::integer),
+        true
+    )
+$$ LANGUAGE sql STABLE;
+
+ALTER POLICY lists_owner ON lists
+    USING ((owner_id = app_user_id() AND app_list_allowed(id)) OR app_maintenance());
+ALTER POLICY todos_owner ON todos
+    USING ((owner_id = app_user_id() AND app_list_allowed(list_id)) OR app_maintenance());
@@ -0,0 +1,19 @@
+DROP POLICY todo_tags_editor ON todo_tags;
+DROP POLICY todo_tags_viewer ON todo_tags;
+CREATE POLICY todo_tags_owner ON todo_tags
+    USING (EXISTS (SELECT 1 FROM todos WHERE todos.id = todo_tags.todo_id));
+
+DROP POLICY tags_editor ON tags;
+DROP POLICY tags_member ON tags;
+
+DROP POLICY todos_editor ON todos;
+DROP POLICY todos_viewer ON todos;
+CREATE POLICY todos_owner ON todos
+    USING ((owner_id = app_user_id() AND app_list_allowed(list_id)) OR app_maintenance());
+
+DROP POLICY lists_admin ON lists;
+DROP POLICY lists_member ON lists;
+
+DROP FUNCTION list_role(INTEGER, INTEGER);
+DROP TABLE list_members;
+DROP TYPE list_role;
@@ -0,0 +1,66 @@
+-- Ordered by what they allow, each role includes the ones before it. The
+-- owner of a list is not a member, 'owner' is only returned by list_role.
+CREATE TYPE list_role AS ENUM ('viewer', 'editor', 'admin', 'owner');
+
+CREATE TABLE list_members (
+    list_id INTEGER NOT NULL REFERENCES lists (id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    role list_role NOT NULL CONSTRAINT list_members_role_check CHECK (role <> 'owner'),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (list_id, user_id)
+);
+
+CREATE INDEX list_members_user_id_idx ON list_members (user_id);
+
+-- The role of a user on a list, NULL when they have none or the API key in
+-- use does not reach the list.
+CREATE FUNCTION list_role(INTEGER, INTEGER) RETURNS list_role AS $$
+    SELECT CASE WHEN l.owner_id = $2 THEN 'owner'::list_role ELSE m.role END
+    FROM lists l
+    LEFT JOIN list_members m ON m.list_id = l.id AND m.user_id = $2
+    WHERE l.id = $1 AND app_list_allowed(l.id)
+$$ LANGUAGE sql STABLE;
+
+-- Memberships are not subject to row level security, the policies on lists
+-- read them and a policy on list_members reading lists would recurse.
+CREATE POLICY lists_member ON lists FOR SELECT
+    USING (app_list_allowed(id) AND EXISTS (
+        SELECT 1 FROM list_members m
+        WHERE m.list_id = lists.id AND m.user_id = app_user_id()
+    ));
+CREATE POLICY lists_admin ON lists FOR UPDATE
+    USING (app_list_allowed(id) AND EXISTS (
+        SELECT 1 FROM list_members m
+        WHERE m.list_id = lists.id AND m.user_id = app_user_id() AND m.role = 'admin'
+    ));
+
+DROP POLICY todos_owner ON todos;
+CREATE POLICY todos_viewer ON todos FOR SELECT
+    USING (list_role(list_id, app_user_id()) IS NOT NULL OR app_maintenance());
+CREATE POLICY todos_editor ON todos
+    USING (list_role(list_id, app_user_id()) >= 'editor' OR app_maintenance());
+
+-- Todos in a shared list carry the tags of the list owner, which members
+-- see and editors may add to.
+CREATE POLICY tags_member ON tags FOR SELECT
+    USING (EXISTS (
+        SELECT 1 FROM list_members m JOIN lists l ON l.id = m.list_id
+        WHERE m.user_id = app_user_id() AND l.owner_id = tags.owner_id
+    ));
+CREATE POLICY tags_editor ON tags FOR INSERT
+    WITH CHECK (EXISTS (
+        SELECT 1 FROM list_members m JOIN lists l ON l.id = m.list_id
+        WHERE m.user_id = app_user_id() AND l.owner_id = tags.owner_id
+            AND m.role >= 'editor'
+    ));
+
+DROP POLICY todo_tags_owner ON todo_tags;
+CREATE POLICY todo_tags_viewer ON todo_tags FOR SELECT
+    USING (EXISTS (SELECT 1 FROM todos WHERE todos.id = todo_tags.todo_id));
+CREATE POLICY todo_tags_editor ON todo_tags
+    USING (app_maintenance() OR EXISTS (
+        SELECT 1 FROM todos
+        WHERE todos.id = todo_tags.todo_id
+            AND list_role(todos.list_id, app_user_id()) >= 'editor'
+    ));
@@ -0,0 +1,4 @@
+DROP TRIGGER todos_record_history ON todos;
+DROP FUNCTION todos_record_history();
+DROP TABLE todo_history;
+DROP FUNCTION todo_history_append_only();
@@ -0,0 +1,81 @@
+-- Every change to a todo, written by a trigger so that changes cascading
+-- to subtasks or from deleted lists are recorded too. Rows are never
+-- changed or removed, and outlive the todos and lists they describe.
+CREATE TABLE todo_history (
+    id BIGSERIAL PRIMARY KEY,
+    todo_id INTEGER NOT NULL,
+    list_id INTEGER NOT NULL,
+    -- NULL for changes the server made on its own.
+    actor_id INTEGER,
+    api_key_id INTEGER,
+    action TEXT NOT NULL CONSTRAINT todo_history_action_check
+        CHECK (action IN ('create', 'update', 'complete', 'delete')),
+    -- The fields of the todo before and after the change. Updates only hold
+    -- the fields that changed.
+    before JSONB,
+    after JSONB,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX todo_history_todo_id_idx ON todo_history (todo_id, id);
+CREATE INDEX todo_history_created_at_idx ON todo_history (created_at, id);
+
+CREATE FUNCTION todos_record_history() RETURNS trigger AS $$
+DECLARE
+    before_fields JSONB;
+    after_fields JSONB;
+    change TEXT;
+    todo todos;
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        change := 'create';
+        todo := NEW;
+        after_fields := to_jsonb(NEW) - 'search_vector' - 'owner_id';
+    ELSIF TG_OP = 'DELETE' THEN
+        change := 'delete';
+        todo := OLD;
+        before_fields := to_jsonb(OLD) - 'search_vector' - 'owner_id';
+    ELSE
+        todo := NEW;
+        SELECT jsonb_object_agg(n.key, o.value), jsonb_object_agg(n.key, n.value)
+        INTO before_fields, after_fields
+        FROM jsonb_each(to_jsonb(NEW) - 'search_vector' - 'owner_id' - 'updated_at') n
+        JOIN jsonb_each(to_jsonb(OLD)) o USING (key)
+        WHERE n.value IS DISTINCT FROM o.value;
+        -- Touching updated_at alone, as tagging does, is not recorded.
+        IF after_fields IS NULL THEN
+            RETURN NULL;
+        END IF;
+        change := CASE WHEN NEW.completed AND NOT OLD.completed THEN 'complete' ELSE 'update' END;
+    END IF;
+
+    INSERT INTO todo_history (todo_id, list_id, actor_id, api_key_id, action, before, after)
+    VALUES (
+        todo.id, todo.list_id, app_user_id(),
+        NULLIF(current_setting('app.api_key_id', true), '')::integer,
+        change, before_fields, after_fields
+    );
+    RETURN NULL;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER todos_record_history
+    AFTER INSERT OR UPDATE OR DELETE ON todos
+    FOR EACH ROW EXECUTE FUNCTION todos_record_history();
+
+CREATE FUNCTION todo_history_append_only() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'todo_history is append-only';
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER todo_history_append_only
+    BEFORE UPDATE OR DELETE ON todo_history
+    FOR EACH ROW EXECUTE FUNCTION todo_history_append_only();
+
+-- The history of a list is visible to whoever can see the list now.
+ALTER TABLE todo_history ENABLE ROW LEVEL SECURITY, FORCE ROW LEVEL SECURITY;
+CREATE POLICY todo_history_viewer ON todo_history FOR SELECT
+    USING (list_role(list_id, app_user_id()) IS NOT NULL OR app_maintenance());
+CREATE POLICY todo_history_insert ON todo_history FOR INSERT
+    WITH CHECK (true);
@@ -0,0 +1,57 @@
+-- Todos in the trash would come back, purge them instead.
+SELECT set_config('app.maintenance', 'on', true);
+DELETE FROM todos WHERE deleted_at IS NOT NULL;
+
+CREATE OR REPLACE FUNCTION todos_record_history() RETURNS trigger AS $$
+DECLARE
+    before_fields JSONB;
+    after_fields JSONB;
+    change TEXT;
+    todo todos;
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        change := 'create';
+        todo := NEW;
+        after_fields := to_jsonb(NEW) - 'search_vector' - 'owner_id';
+    ELSIF TG_OP = 'DELETE' THEN
+        change := 'delete';
+        todo := OLD;
+        before_fields := to_jsonb(OLD) - 'search_vector' - 'owner_id';
+    ELSE
+        todo := NEW;
+        SELECT jsonb_object_agg(n.key, o.value), jsonb_object_agg(n.key, n.value)
+        INTO before_fields, after_fields
+        FROM jsonb_each(to_jsonb(NEW) - 'search_vector' - 'owner_id' - 'updated_at') n
+        JOIN jsonb_each(to_jsonb(OLD)) o USING (key)
+        WHERE n.value IS DISTINCT FROM o.value;
+        -- Touching updated_at alone, as tagging does, is not recorded.
+        IF after_fields IS NULL THEN
+            RETURN NULL;
+        END IF;
+        change := CASE WHEN NEW.completed AND NOT OLD.completed THEN 'complete' ELSE 'update' END;
+    END IF;
+
+    INSERT INTO todo_history (todo_id, list_id, actor_id, api_key_id, action, before, after)
+    VALUES (
+        todo.id, todo.list_id, app_user_id(),
+        NULLIF(current_setting('app.api_key_id', true), '')::integer,
+        change, before_fields, after_fields
+    );
+    RETURN NULL;
+END
+$$ LANGUAGE plpgsql;
+
+-- History has no update policy, and is append-only otherwise.
+ALTER TABLE todo_history NO FORCE ROW LEVEL SECURITY;
+ALTER TABLE todo_history DISABLE TRIGGER todo_history_append_only;
+UPDATE todo_history SET action = 'update' WHERE action = 'restore';
+UPDATE todo_history SET action = 'delete' WHERE action = 'purge';
+ALTER TABLE todo_history ENABLE TRIGGER todo_history_append_only;
+ALTER TABLE todo_history FORCE ROW LEVEL SECURITY;
+
+ALTER TABLE todo_history
+    DROP CONSTRAINT todo_history_action_check,
+    ADD CONSTRAINT todo_history_action_check
+        CHECK (action IN ('create', 'update', 'complete', 'delete'));
+
+ALTER TABLE todos DROP COLUMN deleted_at;
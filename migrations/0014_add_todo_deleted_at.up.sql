@@ -0,0 +1,57 @@
+-- Deleting a todo moves it to the trash, from where it can be restored
+-- until it is purged.
+ALTER TABLE todos ADD COLUMN deleted_at TIMESTAMPTZ;
+
+CREATE INDEX todos_deleted_at_idx ON todos (deleted_at) WHERE deleted_at IS NOT NULL;
+
+ALTER TABLE todo_history
+    DROP CONSTRAINT todo_history_action_check,
+    ADD CONSTRAINT todo_history_action_check CHECK (
+        action IN ('create', 'update', 'complete', 'delete', 'restore', 'purge')
+    );
+
+-- Moving to and from the trash is recorded as delete and restore, removing
+-- a row for good as purge.
+CREATE OR REPLACE FUNCTION todos_record_history() RETURNS trigger AS $$
+DECLARE
+    before_fields JSONB;
+    after_fields JSONB;
+    change TEXT;
+    todo todos;
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        change := 'create';
+        todo := NEW;
+        after_fields := to_jsonb(NEW) - 'search_vector' - 'owner_id';
+    ELSIF TG_OP = 'DELETE' THEN
+        change := CASE WHEN OLD.deleted_at IS NULL THEN 'delete' ELSE 'purge' END;
+        todo := OLD;
+        before_fields := to_jsonb(OLD) - 'search_vector' - 'owner_id';
+    ELSE
+        todo := NEW;
+        SELECT jsonb_object_agg(n.key, o.value), jsonb_object_agg(n.key, n.value)
+        INTO before_fields, after_fields
+        FROM jsonb_each(to_jsonb(NEW) - 'search_vector' - 'owner_id' - 'updated_at') n
+        JOIN jsonb_each(to_jsonb(OLD)) o USING (key)
+        WHERE n.value IS DISTINCT FROM o.value;
+        -- Touching updated_at alone, as tagging does, is not recorded.
+        IF after_fields IS NULL THEN
+            RETURN NULL;
+        END IF;
+        change := CASE
+            WHEN NEW.deleted_at IS NOT NULL AND OLD.deleted_at IS NULL THEN 'delete'
+            WHEN NEW.deleted_at IS NULL AND OLD.deleted_at IS NOT NULL THEN 'restore'
+            WHEN NEW.completed AND NOT OLD.completed THEN 'complete'
+            ELSE 'update'
+        END;
+    END IF;
+
+    INSERT INTO todo_history (todo_id, list_id, actor_id, api_key_id, action, before, after)
+    VALUES (
+        todo.id, todo.list_id, app_user_id(),
+        NULLIF(current_setting('app.api_key_id', true), '')::integer,
+        change, before_fields, after_fields
+    );
+    RETURN NULL;
+END
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,67 @@
+CREATE OR REPLACE FUNCTION todos_timestamps_update() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP = 'UPDATE' THEN
+        IF to_jsonb(NEW) - 'search_vector' - 'updated_at'
+            IS DISTINCT FROM to_jsonb(OLD) - 'search_vector' - 'updated_at' THEN
+            NEW.updated_at := now();
+        END IF;
+    END IF;
+
+    IF NOT NEW.completed THEN
+        NEW.completed_at := NULL;
+    ELSIF TG_OP = 'INSERT' THEN
+        NEW.completed_at := COALESCE(NEW.completed_at, now());
+    ELSIF NOT OLD.completed THEN
+        NEW.completed_at := now();
+    ELSE
+        NEW.completed_at := OLD.completed_at;
+    END IF;
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION todos_record_history() RETURNS trigger AS $$
+DECLARE
+    before_fields JSONB;
+    after_fields JSONB;
+    change TEXT;
+    todo todos;
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        change := 'create';
+        todo := NEW;
+        after_fields := to_jsonb(NEW) - 'search_vector' - 'owner_id';
+    ELSIF TG_OP = 'DELETE' THEN
+        change := CASE WHEN OLD.deleted_at IS NULL THEN 'delete' ELSE 'purge' END;
+        todo := OLD;
+        before_fields := to_jsonb(OLD) - 'search_vector' - 'owner_id';
+    ELSE
+        todo := NEW;
+        SELECT jsonb_object_agg(n.key, o.value), jsonb_object_agg(n.key, n.value)
+        INTO before_fields, after_fields
+        FROM jsonb_each(to_jsonb(NEW) - 'search_vector' - 'owner_id' - 'updated_at') n
+        JOIN jsonb_each(to_jsonb(OLD)) o USING (key)
+        WHERE n.value IS DISTINCT FROM o.value;
+        -- Touching updated_at alone, as tagging does, is not recorded.
+        IF after_fields IS NULL THEN
+            RETURN NULL;
+        END IF;
+        change := CASE
+            WHEN NEW.deleted_at IS NOT NULL AND OLD.deleted_at IS NULL THEN 'delete'
+            WHEN NEW.deleted_at IS NULL AND OLD.deleted_at IS NOT NULL THEN 'restore'
+            WHEN NEW.completed AND NOT OLD.completed THEN 'complete'
+            ELSE 'update'
+        END;
+    END IF;
+
+    INSERT INTO todo_history (todo_id, list_id, actor_id, api_key_id, action, before, after)
+    VALUES (
+        todo.id, todo.list_id, app_user_id(),
+        NULLIF(current_setting('app.api_key_id', true), '')::integer,
+        change, before_fields, after_fields
+    );
+    RETURN NULL;
+END
+$$ LANGUAGE plpgsql;
+
+ALTER TABLE todos DROP COLUMN version;
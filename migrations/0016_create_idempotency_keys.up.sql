@@ -0,0 +1,17 @@
+-- Responses to mutating requests sent with an `Idempotency-Key` header,
+-- replayed when the request is retried. `status`, `headers` and `body` stay
+-- NULL while the first request is still being handled.
+CREATE TABLE idempotency_keys (
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    key TEXT NOT NULL,
+    -- A hash of the method, URI and body, to recognize reuse of the key for
+    -- a different request.
+    fingerprint BYTEA NOT NULL,
+    status SMALLINT,
+    headers JSONB,
+    body BYTEA,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (user_id, key)
+);
+
+CREATE INDEX idempotency_keys_created_at_idx ON idempotency_keys (created_at);
@@ -0,0 +1 @@
+ALTER TABLE todo_history DROP COLUMN xact_id;
@@ -0,0 +1,9 @@
+-- The transaction that wrote each entry. Ids are handed out at insert, not
+-- at commit, so a transaction that commits late can add entries below ids
+-- already read. The audit log only shows entries once every transaction
+-- that could still add earlier ones has finished, and pages by this
+-- column first.
+ALTER TABLE todo_history
+    ADD COLUMN xact_id BIGINT NOT NULL DEFAULT (pg_current_xact_id()::text::bigint);
+
+CREATE INDEX todo_history_xact_id_idx ON todo_history (xact_id, id);
@@ -0,0 +1,4 @@
+-- How long the request that claimed a key may take before a retry can take
+-- the key over, so a request that never finished does not hold its key for
+-- the whole window. NULL once the response is stored.
+ALTER TABLE idempotency_keys ADD COLUMN locked_until TIMESTAMPTZ;
@@ -0,0 +1,2 @@
+DROP TRIGGER todos_bump_ancestors ON todos;
+DROP FUNCTION todos_bump_ancestors();
@@ -0,0 +1,35 @@
+-- The progress of a todo counts its subtasks at every depth, so adding,
+-- completing, trashing, restoring, moving or purging a subtask changes how
+-- each of its ancestors looks. Bump their versions so that their ETags move
+-- too.
+CREATE FUNCTION todos_bump_ancestors() RETURNS trigger AS $$
+DECLARE
+    parents INTEGER[];
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        parents := ARRAY[NEW.parent_id];
+    ELSIF TG_OP = 'DELETE' THEN
+        parents := ARRAY[OLD.parent_id];
+    ELSIF NEW.completed IS DISTINCT FROM OLD.completed
+        OR NEW.deleted_at IS DISTINCT FROM OLD.deleted_at
+        OR NEW.parent_id IS DISTINCT FROM OLD.parent_id THEN
+        parents := ARRAY[OLD.parent_id, NEW.parent_id];
+    ELSE
+        RETURN NULL;
+    END IF;
+
+    -- Bumping an ancestor changes none of the columns above, so this does
+    -- not cascade through the trigger again.
+    WITH RECURSIVE ancestors AS (
+        SELECT id, parent_id FROM todos WHERE id = ANY (parents)
+        UNION
+        SELECT t.id, t.parent_id FROM todos t JOIN ancestors a ON t.id = a.parent_id
+    )
+    UPDATE todos SET version = version + 1 WHERE id IN (SELECT id FROM ancestors);
+    RETURN NULL;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER todos_bump_ancestors
+    AFTER INSERT OR UPDATE OR DELETE ON todos
+    FOR EACH ROW EXECUTE FUNCTION todos_bump_ancestors();
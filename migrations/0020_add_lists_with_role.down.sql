@@ -0,0 +1,14 @@
+ALTER POLICY todos_viewer ON todos
+    USING (list_role(list_id, app_user_id()) IS NOT NULL OR app_maintenance());
+ALTER POLICY todos_editor ON todos
+    USING (list_role(list_id, app_user_id()) >= 'editor' OR app_maintenance());
+ALTER POLICY todo_tags_editor ON todo_tags
+    USING (app_maintenance() OR EXISTS (
+        SELECT 1 FROM todos
+        WHERE todos.id = todo_tags.todo_id
+            AND list_role(todos.list_id, app_user_id()) >= 'editor'
+    ));
+ALTER POLICY todo_history_viewer ON todo_history
+    USING (list_role(list_id, app_user_id()) IS NOT NULL OR app_maintenance());
+
+DROP FUNCTION lists_with_role(INTEGER, list_role);
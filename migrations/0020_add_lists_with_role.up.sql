@@ -0,0 +1,28 @@
+-- The lists a user has at least `min_role` on, reachable with the API key in
+-- use. Comparing list ids against this set looks the lists up once per
+-- query through the owner and member indexes, where calling list_role for
+-- every row looked them up once per todo.
+CREATE FUNCTION lists_with_role(user_id INTEGER, min_role list_role)
+RETURNS TABLE (id INTEGER, role list_role) AS $$
+    SELECT l.id, 'owner'::list_role FROM lists l
+    WHERE l.owner_id = $1 AND app_list_allowed(l.id)
+    UNION ALL
+    SELECT m.list_id, m.role FROM list_members m
+    WHERE m.user_id = $1 AND m.role >= $2 AND app_list_allowed(m.list_id)
+$$ LANGUAGE sql STABLE;
+
+ALTER POLICY todos_viewer ON todos
+    USING (list_id IN (SELECT id FROM lists_with_role(app_user_id(), 'viewer'))
+        OR app_maintenance());
+ALTER POLICY todos_editor ON todos
+    USING (list_id IN (SELECT id FROM lists_with_role(app_user_id(), 'editor'))
+        OR app_maintenance());
+ALTER POLICY todo_tags_editor ON todo_tags
+    USING (app_maintenance() OR EXISTS (
+        SELECT 1 FROM todos
+        WHERE todos.id = todo_tags.todo_id
+            AND todos.list_id IN (SELECT id FROM lists_with_role(app_user_id(), 'editor'))
+    ));
+ALTER POLICY todo_history_viewer ON todo_history
+    USING (list_id IN (SELECT id FROM lists_with_role(app_user_id(), 'viewer'))
+        OR app_maintenance());
@@ -0,0 +1,2 @@
+DROP TRIGGER todos_check_cycle ON todos;
+DROP FUNCTION todos_check_cycle();
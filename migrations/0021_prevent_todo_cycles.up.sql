@@ -0,0 +1,23 @@
+-- A todo cannot be below itself. The handlers lock the ancestors of a new
+-- parent before checking this, the trigger backs them up.
+CREATE FUNCTION todos_check_cycle() RETURNS trigger AS $$
+BEGIN
+    IF EXISTS (
+        WITH RECURSIVE ancestors AS (
+            SELECT id, parent_id FROM todos WHERE id = NEW.parent_id
+            UNION
+            SELECT t.id, t.parent_id FROM todos t JOIN ancestors a ON t.id = a.parent_id
+        )
+        SELECT 1 FROM ancestors WHERE id = NEW.id
+    ) THEN
+        RAISE EXCEPTION 'todo % cannot be below itself', NEW.id
+            USING ERRCODE = 'check_violation', CONSTRAINT = 'todos_parent_cycle_check';
+    END IF;
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER todos_check_cycle
+    BEFORE UPDATE OF parent_id ON todos
+    FOR EACH ROW WHEN (NEW.parent_id IS NOT NULL)
+    EXECUTE FUNCTION todos_check_cycle();
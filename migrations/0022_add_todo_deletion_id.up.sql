@@ -0,0 +1,20 @@
+-- The todos moved to the trash together share a deletion id, so that
+-- restoring one brings back the subtasks deleted with it but not those
+-- deleted earlier on their own, however close together in time. Todos
+-- already in the trash are grouped by when they were deleted, as before.
+ALTER TABLE todos ADD COLUMN deletion_id UUID;
+
+SELECT set_config('app.maintenance', 'on', true);
+ALTER TABLE todos DISABLE TRIGGER todos_timestamps_update;
+ALTER TABLE todos DISABLE TRIGGER todos_record_history;
+UPDATE todos SET deletion_id = deletions.id
+FROM (
+    SELECT deleted_at, gen_random_uuid() AS id
+    FROM todos WHERE deleted_at IS NOT NULL GROUP BY deleted_at
+) deletions
+WHERE todos.deleted_at = deletions.deleted_at;
+ALTER TABLE todos ENABLE TRIGGER todos_record_history;
+ALTER TABLE todos ENABLE TRIGGER todos_timestamps_update;
+
+ALTER TABLE todos ADD CONSTRAINT todos_deletion_id_check
+    CHECK ((deleted_at IS NULL) = (deletion_id IS NULL));
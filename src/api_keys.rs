@@ -0,0 +1,284 @@
+//! API keys for scripts and integrations.
+//!
+//! A key is sent like an access token, as `Authorization: Bearer <key>`, and
+//! is told apart by its `rtk_` prefix. Keys never expire but can be revoked,
+//! are only stored hashed and carry a set of [`Scope`]s. A key may also be
+//! restricted to some lists, in which case it sees no other lists or todos.
+
+use axum::extract::{Path, State};
+use axum::routing::{delete, get};
+use axum::{Json, Router};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::{PgHasArrayType, PgTypeInfo};
+use sqlx::PgPool;
+
+use crate::auth::{self, CurrentUser};
+use crate::db;
+use crate::error::ApiError;
+use crate::validation::{self, TextRules, ValidJson, Validate, ValidationErrors};
+use crate::AppState;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/api-keys", get(get_api_keys).post(add_api_key))
+        .route("/api-keys/:id", delete(revoke_api_key))
+}
+
+/// Marks a bearer token as an API key rather than an access token.
+pub const KEY_PREFIX: &str = "rtk_";
+
+/// How much of a key is kept in the clear to recognize it by.
+const SHOWN_LEN: usize = 12;
+
+/// Something a caller is allowed to do. Reading and writing cover lists,
+/// todos and tags; `admin` implies both and also allows managing API keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "text")]
+pub enum Scope {
+    #[serde(rename = "todos:read")]
+    #[sqlx(rename = "todos:read")]
+    TodosRead,
+    #[serde(rename = "todos:write")]
+    #[sqlx(rename = "todos:write")]
+    TodosWrite,
+    #[serde(rename = "admin")]
+    #[sqlx(rename = "admin")]
+    Admin,
+}
+
+impl Scope {
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::TodosRead => "todos:read",
+            Scope::TodosWrite => "todos:write",
+            Scope::Admin => "admin",
+        }
+    }
+}
+
+impl PgHasArrayType for Scope {
+    fn array_type_info() -> PgTypeInfo {
+        PgTypeInfo::with_name("_text")
+    }
+}
+
+/// A set of scopes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Scopes(u8);
+
+impl Scopes {
+    /// Every scope, as held by access tokens.
+    pub const ALL: Scopes = Scopes(u8::MAX);
+
+    /// Whether the set grants `scope`, directly or through `admin`.
+    pub fn allows(self, scope: Scope) -> bool {
+        self.0 & (scope.bit() | Scope::Admin.bit()) != 0
+    }
+}
+
+impl FromIterator<Scope> for Scopes {
+    fn from_iter<I: IntoIterator<Item = Scope>>(iter: I) -> Self {
+        Scopes(iter.into_iter().fold(0, |bits, scope| bits | scope.bit()))
+    }
+}
+
+/// Look up the user behind an API key and record that the key was used.
+pub async fn authenticate(pool: &PgPool, key: &str) -> Result<CurrentUser, ApiError> {
+    let key = sqlx::query!(
+        r#"
+        UPDATE api_keys SET last_used_at = now()
+        WHERE key_hash = $1
+        RETURNING id, user_id, scopes AS "scopes: Vec<Scope>",
+            list_ids IS NOT NULL AS "restricted!"
+        "#,
+        auth::hash_token(key)
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| ApiError::Unauthorized("The API key is invalid or has been revoked.".into()))?;
+
+    Ok(CurrentUser {
+        id: key.user_id,
+        api_key_id: Some(key.id),
+        scopes: key.scopes.into_iter().collect(),
+        restricted: key.restricted,
+    })
+}
+
+#[derive(Debug, Serialize)]
+struct ApiKey {
+    id: i32,
+    name: String,
+    /// The start of the key, to recognize it by.
+    prefix: String,
+    scopes: Vec<Scope>,
+    /// The lists the key is restricted to, if any.
+    list_ids: Option<Vec<i32>>,
+    created_at: DateTime<Utc>,
+    last_used_at: Option<DateTime<Utc>>,
+}
+
+/// A newly created key. The key itself is only ever shown here.
+#[derive(Debug, Serialize)]
+struct NewApiKey {
+    #[serde(flatten)]
+    api_key: ApiKey,
+    key: String,
+}
+
+const NAME: TextRules = TextRules {
+    min_len: 1,
+    max_len: 100,
+    allow_newlines: false,
+};
+
+/// The most lists a single key can be restricted to.
+const MAX_LISTS: usize = 100;
+
+#[derive(Debug, Deserialize)]
+struct CreateApiKey {
+    name: String,
+    scopes: Vec<Scope>,
+    /// Defaults to the lists of the key making the request, if it is
+    /// restricted, so that a key cannot mint a broader one.
+    list_ids: Option<Vec<i32>>,
+}
+
+impl Validate for CreateApiKey {
+    fn validate(&mut self, errors: &mut ValidationErrors) {
+        validation::text(errors, "name", &mut self.name, &NAME);
+        self.scopes.sort_by_key(|scope| *scope as u8);
+        self.scopes.dedup();
+        if self.scopes.is_empty() {
+            errors.add("scopes", "blank", "must contain at least one scope");
+        }
+        if let Some(list_ids) = &mut self.list_ids {
+            list_ids.sort_unstable();
+            list_ids.dedup();
+            if list_ids.is_empty() {
+                errors.add("list_ids", "blank", "must contain at least one list");
+            }
+            if list_ids.len() > MAX_LISTS {
+                errors.add(
+                    "list_ids",
+                    "too_many",
+                    format!("must have at most {MAX_LISTS} lists"),
+                );
+            }
+        }
+    }
+}
+
+/// The keys of the caller. A key restricted to some lists only sees the keys
+/// restricted to some of those lists, as it could have created them.
+async fn get_api_keys(
+    State(pool): State<PgPool>,
+    user: CurrentUser,
+) -> Result<Json<Vec<ApiKey>>, ApiError> {
+    user.require(Scope::Admin)?;
+    let keys = sqlx::query_as!(
+        ApiKey,
+        r#"
+        SELECT id, name, prefix, scopes AS "scopes: Vec<Scope>", list_ids, created_at,
+            last_used_at
+        FROM api_keys
+        WHERE user_id = $1
+            AND (NOT $2 OR list_ids <@ (SELECT list_ids FROM api_keys WHERE id = $3))
+        ORDER BY id
+        "#,
+        user.id,
+        user.restricted,
+        user.api_key_id
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    Ok(Json(keys))
+}
+
+async fn add_api_key(
+    State(pool): State<PgPool>,
+    user: CurrentUser,
+    ValidJson(input): ValidJson<CreateApiKey>,
+) -> Result<Json<NewApiKey>, ApiError> {
+    user.require(Scope::Admin)?;
+    let mut tx = db::begin(&pool, user).await?;
+    if let Some(list_ids) = &input.list_ids {
+        // Only lists the caller can see, which excludes those outside the
+        // restriction of the key making the request.
+        let found = sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(*) AS "count!" FROM lists
+            WHERE id = ANY($2) AND list_role(id, $1) IS NOT NULL
+            "#,
+            user.id,
+            list_ids
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        if found != list_ids.len() as i64 {
+            let mut errors = ValidationErrors::default();
+            errors.add("list_ids", "not_found", "must all be existing lists");
+            return Err(ApiError::Validation(errors));
+        }
+    }
+
+    let key = format!("{KEY_PREFIX}{}", auth::random_token());
+    let api_key = sqlx::query_as!(
+        ApiKey,
+        r#"
+        INSERT INTO api_keys (user_id, name, prefix, key_hash, scopes, list_ids)
+        VALUES (
+            $1, $2, $3, $4, $5,
+            COALESCE($6, (SELECT list_ids FROM api_keys WHERE id = $7))
+        )
+        RETURNING id, name, prefix, scopes AS "scopes: Vec<Scope>", list_ids, created_at,
+            last_used_at
+        "#,
+        user.id,
+        input.name,
+        &key[..SHOWN_LEN],
+        auth::hash_token(&key),
+        &input.scopes as &[Scope],
+        input.list_ids.as_deref(),
+        user.api_key_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(Json(NewApiKey { api_key, key }))
+}
+
+/// Revoke a key, which stops working immediately. A key restricted to some
+/// lists can only revoke the keys it can see.
+async fn revoke_api_key(
+    State(pool): State<PgPool>,
+    user: CurrentUser,
+    Path(id): Path<i32>,
+) -> Result<Json<ApiKey>, ApiError> {
+    user.require(Scope::Admin)?;
+    let api_key = sqlx::query_as!(
+        ApiKey,
+        r#"
+        DELETE FROM api_keys
+        WHERE id = $1 AND user_id = $2
+            AND (NOT $3 OR list_ids <@ (SELECT list_ids FROM api_keys WHERE id = $4))
+        RETURNING id, name, prefix, scopes AS "scopes: Vec<Scope>", list_ids, created_at,
+            last_used_at
+        "#,
+        id,
+        user.id,
+        user.restricted,
+        user.api_key_id
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    Ok(Json(api_key))
+}
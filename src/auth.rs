@@ -0,0 +1,423 @@
+//! User accounts and token authentication.
+//!
+//! Passwords are hashed with Argon2. Logging in yields a short-lived signed
+//! access token, sent as `Authorization: Bearer <token>`, and a long-lived
+//! refresh token that can be exchanged once for a new pair. Refresh tokens
+//! are random and only stored hashed. API keys, see [`crate::api_keys`],
+//! are sent the same way and resolve to the same [`CurrentUser`].
+
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use axum::async_trait;
+use axum::extract::{FromRef, FromRequestParts, State};
+use axum::http::request::Parts;
+use axum::http::{header, Method, StatusCode};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+
+use crate::api_keys::{self, Scope, Scopes};
+use crate::config::AuthConfig;
+use crate::db;
+use crate::error::ApiError;
+use crate::lists;
+use crate::validation::{self, TextRules, ValidJson, Validate, ValidationErrors};
+use crate::AppState;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/auth/register", post(register))
+        .route("/auth/login", post(login))
+        .route("/auth/refresh", post(refresh))
+        .route("/auth/logout", post(logout))
+        .route("/auth/me", get(me))
+}
+
+/// The authenticated caller, extracted from a bearer access token or API key.
+///
+/// Safe methods need the `todos:read` scope and every other method
+/// `todos:write`; handlers check anything beyond that with [`Self::require`].
+#[derive(Debug, Clone, Copy)]
+pub struct CurrentUser {
+    pub id: i32,
+    /// The API key the request was made with, if any.
+    pub api_key_id: Option<i32>,
+    pub scopes: Scopes,
+    /// Whether the API key is restricted to some lists.
+    pub restricted: bool,
+}
+
+impl CurrentUser {
+    /// A user acting on their own behalf, with every scope.
+    pub fn new(id: i32) -> Self {
+        CurrentUser {
+            id,
+            api_key_id: None,
+            scopes: Scopes::ALL,
+            restricted: false,
+        }
+    }
+
+    pub fn require(self, scope: Scope) -> Result<(), ApiError> {
+        if self.scopes.allows(scope) {
+            Ok(())
+        } else {
+            Err(ApiError::Forbidden(format!(
+                "The API key lacks the {} scope.",
+                scope.as_str()
+            )))
+        }
+    }
+
+    /// Check that the caller is not restricted to some lists, for changes
+    /// that reach beyond them, such as renaming a tag used everywhere.
+    pub fn require_unrestricted(self) -> Result<(), ApiError> {
+        if self.restricted {
+            Err(ApiError::Forbidden(
+                "The API key is restricted to some lists.".into(),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for CurrentUser
+where
+    S: Send + Sync,
+    AppState: FromRef<S>,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        // Routes are guarded by a middleware running this extractor, so the
+        // handler finds the caller already authenticated.
+        if let Some(user) = parts.extensions.get::<CurrentUser>() {
+            return Ok(*user);
+        }
+        let state = AppState::from_ref(state);
+        let token = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| ApiError::Unauthorized("A bearer token is required.".into()))?
+            .trim();
+        let user = if token.starts_with(api_keys::KEY_PREFIX) {
+            api_keys::authenticate(&state.pool, token).await?
+        } else {
+            CurrentUser::new(verify_access_token(&state.config.auth, token)?.sub)
+        };
+        match parts.method {
+            Method::GET | Method::HEAD => user.require(Scope::TodosRead)?,
+            _ => user.require(Scope::TodosWrite)?,
+        }
+        parts.extensions.insert(user);
+        Ok(user)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Claims {
+    sub: i32,
+    iat: i64,
+    exp: i64,
+}
+
+fn verify_access_token(config: &AuthConfig, token: &str) -> Result<Claims, ApiError> {
+    let key = DecodingKey::from_secret(config.secret.as_bytes());
+    jsonwebtoken::decode::<Claims>(token, &key, &Validation::default())
+        .map(|data| data.claims)
+        .map_err(|err| {
+            tracing::debug!("Rejected access token: {}", err);
+            ApiError::Unauthorized("The access token is invalid or has expired.".into())
+        })
+}
+
+#[derive(Debug, Serialize)]
+struct Tokens {
+    access_token: String,
+    token_type: &'static str,
+    /// Seconds until the access token expires.
+    expires_in: u64,
+    refresh_token: String,
+}
+
+/// Sign a new access token and store a new refresh token for `user_id`.
+async fn issue_tokens(
+    pool: &PgPool,
+    config: &AuthConfig,
+    user_id: i32,
+) -> Result<Tokens, ApiError> {
+    let now = Utc::now();
+    let ttl = config.access_token_ttl();
+    let claims = Claims {
+        sub: user_id,
+        iat: now.timestamp(),
+        exp: now.timestamp() + ttl.as_secs() as i64,
+    };
+    let key = EncodingKey::from_secret(config.secret.as_bytes());
+    let access_token = jsonwebtoken::encode(&Header::default(), &claims, &key)
+        .expect("HS256 signing does not fail");
+
+    let refresh_token = random_token();
+    let expires_at = now + config.refresh_token_ttl();
+    sqlx::query!(
+        "INSERT INTO refresh_tokens (token_hash, user_id, expires_at) VALUES ($1, $2, $3)",
+        hash_token(&refresh_token),
+        user_id,
+        expires_at
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(Tokens {
+        access_token,
+        token_type: "Bearer",
+        expires_in: ttl.as_secs(),
+        refresh_token,
+    })
+}
+
+/// 256 random bits, base64url encoded.
+pub fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Tokens are random enough that a plain digest keeps them safe at rest.
+pub fn hash_token(token: &str) -> Vec<u8> {
+    Sha256::digest(token.as_bytes()).to_vec()
+}
+
+async fn hash_password(password: String) -> String {
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .expect("hashing with a fresh salt does not fail")
+            .to_string()
+    })
+    .await
+    .expect("password hashing panicked")
+}
+
+/// A hash of no one's password, made with the default parameters, which
+/// logins for unknown emails are checked against so that they take as long
+/// as logins for known ones.
+const DUMMY_PASSWORD_HASH: &str =
+    "$argon2id$v=19$m=19456,t=2,p=1$adzWVJLnkzK3o1yyyXb1mA$pNu3gZfjJFNO++wbIIoQPqd9b5PyMtTIgWE+bX6nc8M";
+
+async fn verify_password(password: String, hash: String) -> bool {
+    tokio::task::spawn_blocking(move || {
+        PasswordHash::new(&hash).is_ok_and(|hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+    })
+    .await
+    .expect("password verification panicked")
+}
+
+#[derive(Debug, Serialize)]
+pub struct User {
+    pub id: i32,
+    pub email: String,
+    created_at: DateTime<Utc>,
+}
+
+const EMAIL: TextRules = TextRules {
+    min_len: 3,
+    max_len: 254,
+    allow_newlines: false,
+};
+
+const MIN_PASSWORD_LEN: usize = 8;
+const MAX_PASSWORD_LEN: usize = 1024;
+
+#[derive(Debug, Deserialize)]
+pub struct Credentials {
+    pub email: String,
+    pub password: String,
+}
+
+impl Validate for Credentials {
+    fn validate(&mut self, errors: &mut ValidationErrors) {
+        validation::text(errors, "email", &mut self.email, &EMAIL);
+        if !self.email.contains('@') {
+            errors.add("email", "invalid_format", "must be an email address");
+        }
+        // Passwords are taken verbatim, only their length is checked.
+        let len = self.password.chars().count();
+        if !(MIN_PASSWORD_LEN..=MAX_PASSWORD_LEN).contains(&len) {
+            errors.add(
+                "password",
+                "invalid_length",
+                format!("must be between {MIN_PASSWORD_LEN} and {MAX_PASSWORD_LEN} characters"),
+            );
+        }
+    }
+}
+
+async fn register(
+    State(pool): State<PgPool>,
+    ValidJson(input): ValidJson<Credentials>,
+) -> Result<Json<User>, ApiError> {
+    let user = create_user(&pool, input, true).await?;
+
+    Ok(Json(user))
+}
+
+/// Create a user from validated credentials, with an inbox if `inbox`.
+pub async fn create_user(pool: &PgPool, input: Credentials, inbox: bool) -> Result<User, ApiError> {
+    let password_hash = hash_password(input.password).await;
+    let mut tx = pool.begin().await?;
+    let user = sqlx::query_as!(
+        User,
+        r#"
+        INSERT INTO users (email, password_hash)
+        VALUES ($1, $2)
+        RETURNING id, email, created_at
+        "#,
+        input.email,
+        password_hash
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if inbox {
+        let current = CurrentUser::new(user.id);
+        db::set_user(&mut tx, current).await?;
+        lists::create_inbox(&mut tx, current).await?;
+    }
+    tx.commit().await?;
+
+    Ok(user)
+}
+
+async fn login(
+    State(state): State<AppState>,
+    ValidJson(input): ValidJson<Credentials>,
+) -> Result<Json<Tokens>, ApiError> {
+    let user = sqlx::query!(
+        "SELECT id, password_hash FROM users WHERE lower(email) = lower($1)",
+        input.email
+    )
+    .fetch_optional(&state.pool)
+    .await?;
+    let verified = match user {
+        Some(user) => verify_password(input.password, user.password_hash)
+            .await
+            .then_some(user.id),
+        None => {
+            verify_password(input.password, DUMMY_PASSWORD_HASH.into()).await;
+            None
+        }
+    };
+    let Some(user_id) = verified else {
+        return Err(ApiError::Unauthorized(
+            "The email or password is incorrect.".into(),
+        ));
+    };
+
+    sqlx::query!(
+        "DELETE FROM refresh_tokens WHERE user_id = $1 AND expires_at <= now()",
+        user_id
+    )
+    .execute(&state.pool)
+    .await?;
+    let tokens = issue_tokens(&state.pool, &state.config.auth, user_id).await?;
+
+    Ok(Json(tokens))
+}
+
+#[derive(Debug, Deserialize)]
+struct RefreshToken {
+    refresh_token: String,
+}
+
+impl Validate for RefreshToken {
+    fn validate(&mut self, _errors: &mut ValidationErrors) {}
+}
+
+/// Exchange a refresh token for a new token pair. The old refresh token is
+/// used up.
+async fn refresh(
+    State(state): State<AppState>,
+    ValidJson(input): ValidJson<RefreshToken>,
+) -> Result<Json<Tokens>, ApiError> {
+    let user_id = sqlx::query_scalar!(
+        r#"
+        DELETE FROM refresh_tokens
+        WHERE token_hash = $1 AND expires_at > now()
+        RETURNING user_id
+        "#,
+        hash_token(&input.refresh_token)
+    )
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or_else(|| ApiError::Unauthorized("The refresh token is invalid or has expired.".into()))?;
+    let tokens = issue_tokens(&state.pool, &state.config.auth, user_id).await?;
+
+    Ok(Json(tokens))
+}
+
+async fn logout(
+    State(pool): State<PgPool>,
+    ValidJson(input): ValidJson<RefreshToken>,
+) -> Result<StatusCode, ApiError> {
+    sqlx::query!(
+        "DELETE FROM refresh_tokens WHERE token_hash = $1",
+        hash_token(&input.refresh_token)
+    )
+    .execute(&pool)
+    .await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn me(State(pool): State<PgPool>, user: CurrentUser) -> Result<Json<User>, ApiError> {
+    let user = sqlx::query_as!(
+        User,
+        "SELECT id, email, created_at FROM users WHERE id = $1",
+        user.id
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    Ok(Json(user))
+}
+
+#[cfg(test)]
+mod tests {
+    use argon2::Params;
+
+    use super::*;
+
+    #[test]
+    fn dummy_hash_costs_as_much_as_a_real_one() {
+        let dummy = PasswordHash::new(DUMMY_PASSWORD_HASH).unwrap();
+        let params = Params::try_from(&dummy).unwrap();
+        let defaults = Params::default();
+        assert_eq!(dummy.algorithm, argon2::Algorithm::default().ident());
+        assert_eq!(
+            (params.m_cost(), params.t_cost(), params.p_cost()),
+            (defaults.m_cost(), defaults.t_cost(), defaults.p_cost())
+        );
+    }
+
+    #[tokio::test]
+    async fn dummy_hash_matches_no_password() {
+        assert!(!verify_password("hunter22hunter".into(), DUMMY_PASSWORD_HASH.into()).await);
+    }
+}
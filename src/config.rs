@@ -0,0 +1,463 @@
+//! Typed application configuration.
+//!
+//! Values are merged from, in increasing order of precedence:
+//!
+//! 1. the built-in defaults below,
+//! 2. the TOML config file (`rust-todo.toml`, or the path given by `--config`
+//!    or `TODO_CONFIG`),
+//! 3. a `.env` file in the working directory,
+//! 4. the process environment.
+//!
+//! Environment variables use the `TODO_` prefix with `__` separating nested
+//! keys, e.g. `TODO_DATABASE__MAX_CONNECTIONS=10`. The conventional
+//! `DATABASE_URL` and `RUST_LOG` variables are honoured as well.
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use axum::http::HeaderValue;
+use figment::providers::{Env, Format, Serialized, Toml};
+use figment::Figment;
+use serde::{Deserialize, Serialize};
+use tracing_subscriber::EnvFilter;
+
+const DEFAULT_CONFIG_FILE: &str = "rust-todo.toml";
+
+/// Upper bounds for the token lifetimes, a day and ten years, which also
+/// keep expiry times well within what timestamps can hold.
+const MAX_ACCESS_TOKEN_TTL: u64 = 24 * 60 * 60;
+const MAX_REFRESH_TOKEN_TTL: u64 = 10 * 366 * 24 * 60 * 60;
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Config {
+    pub database: DatabaseConfig,
+    pub server: ServerConfig,
+    pub log: LogConfig,
+    pub pagination: PaginationConfig,
+    pub search: SearchConfig,
+    pub auth: AuthConfig,
+    pub trash: TrashConfig,
+    pub cache: CacheConfig,
+    pub idempotency: IdempotencyConfig,
+    pub features: Features,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct DatabaseConfig {
+    pub url: String,
+    pub max_connections: u32,
+    pub min_connections: u32,
+    /// How long to wait for a free connection before giving up.
+    pub acquire_timeout_secs: u64,
+    /// Close connections that have been idle this long. Unset keeps them open.
+    pub idle_timeout_secs: Option<u64>,
+    /// Recycle connections after this long. Unset keeps them forever.
+    pub max_lifetime_secs: Option<u64>,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            max_connections: 5,
+            min_connections: 0,
+            acquire_timeout_secs: 30,
+            idle_timeout_secs: Some(600),
+            max_lifetime_secs: Some(1800),
+        }
+    }
+}
+
+impl DatabaseConfig {
+    pub fn acquire_timeout(&self) -> Duration {
+        Duration::from_secs(self.acquire_timeout_secs)
+    }
+
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout_secs.map(Duration::from_secs)
+    }
+
+    pub fn max_lifetime(&self) -> Option<Duration> {
+        self.max_lifetime_secs.map(Duration::from_secs)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub bind: SocketAddr,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: SocketAddr::from(([127, 0, 0, 1], 3000)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct LogConfig {
+    /// A `tracing` filter directive, same syntax as `RUST_LOG`.
+    pub filter: String,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            filter: "info,sqlx::postgres::notice=warn".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PaginationConfig {
+    /// Page size used when a request does not pass `limit`.
+    pub default_limit: u32,
+    /// Larger `limit` values are clamped to this.
+    pub max_limit: u32,
+}
+
+impl Default for PaginationConfig {
+    fn default() -> Self {
+        Self {
+            default_limit: 20,
+            max_limit: 100,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SearchConfig {
+    /// Postgres text search configuration, e.g. `english` or `simple`.
+    /// Changing it rebuilds the search index on the next start.
+    pub language: String,
+    /// Markers placed around matches in result snippets.
+    pub highlight_start: String,
+    pub highlight_stop: String,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        Self {
+            language: "english".to_string(),
+            highlight_start: "<mark>".to_string(),
+            highlight_stop: "</mark>".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    /// The key access tokens are signed with. Required, at least 32 bytes;
+    /// changing it signs every user out.
+    pub secret: String,
+    pub access_token_ttl_secs: u64,
+    pub refresh_token_ttl_secs: u64,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            secret: String::new(),
+            access_token_ttl_secs: 15 * 60,
+            refresh_token_ttl_secs: 30 * 24 * 60 * 60,
+        }
+    }
+}
+
+impl AuthConfig {
+    pub fn access_token_ttl(&self) -> Duration {
+        Duration::from_secs(self.access_token_ttl_secs)
+    }
+
+    pub fn refresh_token_ttl(&self) -> Duration {
+        Duration::from_secs(self.refresh_token_ttl_secs)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TrashConfig {
+    /// Deleted todos are purged for good after this many days in the trash.
+    pub retention_days: u32,
+    /// How often to look for todos past their retention.
+    pub purge_interval_secs: u64,
+}
+
+impl Default for TrashConfig {
+    fn default() -> Self {
+        Self {
+            retention_days: 30,
+            purge_interval_secs: 60 * 60,
+        }
+    }
+}
+
+impl TrashConfig {
+    pub fn purge_interval(&self) -> Duration {
+        Duration::from_secs(self.purge_interval_secs)
+    }
+}
+
+/// `Cache-Control` policies for reads. The defaults let clients keep a copy
+/// but have them revalidate it, which is cheap with `If-None-Match`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    /// Sent with a single todo.
+    pub todo: String,
+    /// Sent with pages of todos.
+    pub todo_pages: String,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            todo: "private, no-cache".to_string(),
+            todo_pages: "private, no-cache".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct IdempotencyConfig {
+    /// How long a response is replayed for retries with the same
+    /// `Idempotency-Key`, after which the key can be used again.
+    pub window_secs: u64,
+    /// How long a request may hold its key before a retry with the same key
+    /// can take it over.
+    pub lease_secs: u64,
+    /// How often to forget keys past their window.
+    pub purge_interval_secs: u64,
+}
+
+impl Default for IdempotencyConfig {
+    fn default() -> Self {
+        Self {
+            window_secs: 24 * 60 * 60,
+            lease_secs: 60,
+            purge_interval_secs: 60 * 60,
+        }
+    }
+}
+
+impl IdempotencyConfig {
+    pub fn purge_interval(&self) -> Duration {
+        Duration::from_secs(self.purge_interval_secs)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Features {
+    /// Log every HTTP request through `tower_http::trace`.
+    pub request_tracing: bool,
+    /// Apply pending schema migrations before serving.
+    pub auto_migrate: bool,
+}
+
+impl Default for Features {
+    fn default() -> Self {
+        Self {
+            request_tracing: true,
+            auto_migrate: true,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("failed to read .env: {0}")]
+    DotEnv(#[from] dotenvy::Error),
+    #[error("config file {0} does not exist")]
+    MissingFile(PathBuf),
+    #[error("failed to load configuration: {0}")]
+    Load(#[from] Box<figment::Error>),
+    #[error("invalid configuration:\n{0}")]
+    Invalid(Problems),
+}
+
+/// Every validation failure found, reported together.
+#[derive(Debug)]
+pub struct Problems(Vec<String>);
+
+impl fmt::Display for Problems {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, problem) in self.0.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "  - {problem}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Config {
+    /// Load the configuration from all sources and validate it. An explicit
+    /// `path` takes precedence over `TODO_CONFIG`. The auth secret is only
+    /// required when `serving`, so that migrations can run without it.
+    pub fn load(path: Option<PathBuf>, serving: bool) -> Result<Self, ConfigError> {
+        match dotenvy::dotenv() {
+            Ok(_) => {}
+            Err(err) if err.not_found() => {}
+            Err(err) => return Err(err.into()),
+        }
+
+        let (path, explicit) =
+            match path.or_else(|| std::env::var_os("TODO_CONFIG").map(Into::into)) {
+                Some(path) => (path, true),
+                None => (PathBuf::from(DEFAULT_CONFIG_FILE), false),
+            };
+        if explicit && !path.exists() {
+            return Err(ConfigError::MissingFile(path));
+        }
+
+        let config: Config = Figment::from(Serialized::defaults(Config::default()))
+            .merge(Toml::file(&path))
+            .merge(
+                Env::raw()
+                    .only(&["DATABASE_URL"])
+                    .map(|_| "database.url".into()),
+            )
+            .merge(Env::raw().only(&["RUST_LOG"]).map(|_| "log.filter".into()))
+            .merge(Env::prefixed("TODO_").ignore(&["CONFIG"]).split("__"))
+            .extract()
+            .map_err(Box::new)?;
+
+        config.validate(serving)?;
+        Ok(config)
+    }
+
+    fn validate(&self, serving: bool) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+
+        let db = &self.database;
+        if db.url.is_empty() {
+            problems.push(
+                "database.url is not set (use DATABASE_URL or the [database] table)".to_string(),
+            );
+        } else if !(db.url.starts_with("postgres://") || db.url.starts_with("postgresql://")) {
+            problems.push("database.url must start with postgres:// or postgresql://".to_string());
+        }
+        if db.max_connections == 0 {
+            problems.push("database.max_connections must be at least 1".to_string());
+        }
+        if db.min_connections > db.max_connections {
+            problems.push(format!(
+                "database.min_connections ({}) exceeds database.max_connections ({})",
+                db.min_connections, db.max_connections
+            ));
+        }
+        if db.acquire_timeout_secs == 0 {
+            problems.push("database.acquire_timeout_secs must be greater than 0".to_string());
+        }
+        if db.idle_timeout_secs == Some(0) {
+            problems.push("database.idle_timeout_secs must be greater than 0 when set".to_string());
+        }
+        if db.max_lifetime_secs == Some(0) {
+            problems.push("database.max_lifetime_secs must be greater than 0 when set".to_string());
+        }
+
+        if let Err(err) = EnvFilter::try_new(&self.log.filter) {
+            problems.push(format!(
+                "log.filter {:?} is invalid: {err}",
+                self.log.filter
+            ));
+        }
+
+        let page = &self.pagination;
+        if page.default_limit == 0 {
+            problems.push("pagination.default_limit must be at least 1".to_string());
+        }
+        if page.default_limit > page.max_limit {
+            problems.push(format!(
+                "pagination.default_limit ({}) exceeds pagination.max_limit ({})",
+                page.default_limit, page.max_limit
+            ));
+        }
+
+        let search = &self.search;
+        let is_identifier = |part: &str| {
+            !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        };
+        if !search.language.split('.').all(is_identifier) {
+            problems.push(format!(
+                "search.language {:?} is not a text search configuration name",
+                search.language
+            ));
+        }
+
+        let auth = &self.auth;
+        if serving {
+            if auth.secret.is_empty() {
+                problems.push(
+                    "auth.secret is not set (use TODO_AUTH__SECRET or the [auth] table)"
+                        .to_string(),
+                );
+            } else if auth.secret.len() < 32 {
+                problems.push("auth.secret must be at least 32 bytes long".to_string());
+            }
+        }
+        if auth.access_token_ttl_secs == 0 || auth.access_token_ttl_secs > MAX_ACCESS_TOKEN_TTL {
+            problems.push(format!(
+                "auth.access_token_ttl_secs must be between 1 and {MAX_ACCESS_TOKEN_TTL}"
+            ));
+        }
+        if auth.refresh_token_ttl_secs > MAX_REFRESH_TOKEN_TTL {
+            problems.push(format!(
+                "auth.refresh_token_ttl_secs must be at most {MAX_REFRESH_TOKEN_TTL}"
+            ));
+        }
+        if auth.refresh_token_ttl_secs <= auth.access_token_ttl_secs {
+            problems.push(format!(
+                "auth.refresh_token_ttl_secs ({}) must exceed auth.access_token_ttl_secs ({})",
+                auth.refresh_token_ttl_secs, auth.access_token_ttl_secs
+            ));
+        }
+
+        let trash = &self.trash;
+        if trash.retention_days == 0 || trash.retention_days > i32::MAX as u32 {
+            problems.push("trash.retention_days must be between 1 and 2147483647".to_string());
+        }
+        if trash.purge_interval_secs == 0 {
+            problems.push("trash.purge_interval_secs must be greater than 0".to_string());
+        }
+
+        for (key, value) in [
+            ("cache.todo", &self.cache.todo),
+            ("cache.todo_pages", &self.cache.todo_pages),
+        ] {
+            if HeaderValue::from_str(value).is_err() {
+                problems.push(format!("{key} {value:?} is not a valid header value"));
+            }
+        }
+
+        let idempotency = &self.idempotency;
+        if idempotency.window_secs == 0 || idempotency.window_secs > i32::MAX as u64 {
+            problems.push("idempotency.window_secs must be between 1 and 2147483647".to_string());
+        }
+        if idempotency.lease_secs == 0 || idempotency.lease_secs > i32::MAX as u64 {
+            problems.push("idempotency.lease_secs must be between 1 and 2147483647".to_string());
+        }
+        if idempotency.purge_interval_secs == 0 {
+            problems.push("idempotency.purge_interval_secs must be greater than 0".to_string());
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Invalid(Problems(problems)))
+        }
+    }
+}
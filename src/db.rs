@@ -0,0 +1,62 @@
+//! Transactions scoped for row level security.
+//!
+//! Lists, todos and tags are only visible to a transaction that has
+//! `app.user_id` set to their owner, see migration 0010. Queries on those
+//! tables go through [`begin`] and still filter by owner themselves. With
+//! an API key, `app.api_key_id` is set as well and hides the lists the key
+//! is not restricted to, see migration 0011.
+
+use sqlx::{PgConnection, PgPool, Postgres, Transaction};
+
+use crate::auth::CurrentUser;
+
+/// Begin a transaction acting on behalf of `user`.
+pub async fn begin(
+    pool: &PgPool,
+    user: CurrentUser,
+) -> Result<Transaction<'static, Postgres>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    set_user(&mut tx, user).await?;
+    Ok(tx)
+}
+
+/// Act on behalf of `user` for the rest of the current transaction.
+pub async fn set_user(conn: &mut PgConnection, user: CurrentUser) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        SELECT set_config('app.user_id', $1, true) AS user_id,
+            set_config('app.api_key_id', $2, true) AS api_key_id
+        "#,
+        user.id.to_string(),
+        user.api_key_id.map(|id| id.to_string()).unwrap_or_default()
+    )
+    .fetch_one(conn)
+    .await?;
+    Ok(())
+}
+
+/// Begin a transaction that sees the rows of every user, for maintenance
+/// the server does on its own.
+pub async fn begin_maintenance(
+    pool: &PgPool,
+) -> Result<Transaction<'static, Postgres>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query_scalar!("SELECT set_config('app.maintenance', 'on', true)")
+        .fetch_one(&mut *tx)
+        .await?;
+    Ok(tx)
+}
+
+/// Whether the database role ignores row level security, in which case it
+/// only backs up the ownership checks in the handlers on paper.
+pub async fn bypasses_rls(pool: &PgPool) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT rolsuper OR rolbypassrls AS "bypasses!"
+        FROM pg_roles
+        WHERE rolname = current_user
+        "#
+    )
+    .fetch_one(pool)
+    .await
+}
@@ -0,0 +1,171 @@
+//! The error type returned by every handler.
+//!
+//! Errors are rendered as RFC 7807 `application/problem+json` documents.
+//! Database errors are classified by kind and never echoed to the client;
+//! the underlying cause is logged instead.
+
+use axum::extract::rejection::JsonRejection;
+use axum::http::{header, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::Serialize;
+use sqlx::error::ErrorKind;
+
+use crate::validation::ValidationErrors;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ApiError {
+    /// Missing or invalid credentials.
+    #[error("unauthorized: {0}")]
+    Unauthorized(String),
+    /// Valid credentials that do not allow the request.
+    #[error("forbidden: {0}")]
+    Forbidden(String),
+    #[error("resource not found")]
+    NotFound,
+    /// An `If-Match` precondition did not hold.
+    #[error("precondition failed")]
+    PreconditionFailed,
+    #[error("conflict: {0}")]
+    Conflict(String),
+    #[error("unprocessable: {0}")]
+    Unprocessable(String),
+    #[error("validation failed")]
+    Validation(ValidationErrors),
+    /// The request was rejected by an extractor before reaching a handler.
+    #[error("rejected: {1}")]
+    Rejected(StatusCode, String),
+    #[error("database unavailable: {0}")]
+    Unavailable(#[source] sqlx::Error),
+    #[error("internal error: {0}")]
+    Internal(#[source] sqlx::Error),
+}
+
+/// A problem details document as described by RFC 7807.
+#[derive(Debug, Serialize)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub title: &'static str,
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub errors: Option<ValidationErrors>,
+}
+
+impl ApiError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::NotFound => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+            ApiError::Unprocessable(_) | ApiError::Validation(_) => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+            ApiError::Rejected(status, _) => *status,
+            ApiError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn problem(self) -> Problem {
+        let status = self.status();
+        let mut errors = None;
+        let detail = match self {
+            ApiError::NotFound => "The requested resource does not exist.".to_string(),
+            ApiError::PreconditionFailed => {
+                "The resource has changed since it was last read.".to_string()
+            }
+            ApiError::Unauthorized(detail)
+            | ApiError::Forbidden(detail)
+            | ApiError::Conflict(detail)
+            | ApiError::Unprocessable(detail)
+            | ApiError::Rejected(_, detail) => detail,
+            ApiError::Validation(fields) => {
+                errors = Some(fields);
+                "One or more fields are invalid.".to_string()
+            }
+            ApiError::Unavailable(_) => {
+                "The service is temporarily unavailable, try again later.".to_string()
+            }
+            ApiError::Internal(_) => "An unexpected error occurred.".to_string(),
+        };
+        Problem {
+            kind: "about:blank",
+            title: status.canonical_reason().unwrap_or("Error"),
+            status: status.as_u16(),
+            detail: Some(detail),
+            errors,
+        }
+    }
+}
+
+impl From<sqlx::Error> for ApiError {
+    fn from(error: sqlx::Error) -> Self {
+        match &error {
+            sqlx::Error::RowNotFound => ApiError::NotFound,
+            sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed | sqlx::Error::Io(_) => {
+                ApiError::Unavailable(error)
+            }
+            sqlx::Error::Database(db) => match db.kind() {
+                ErrorKind::UniqueViolation => {
+                    ApiError::Conflict("A resource with the same value already exists.".into())
+                }
+                ErrorKind::ForeignKeyViolation => {
+                    ApiError::Unprocessable("The request references a missing resource.".into())
+                }
+                ErrorKind::NotNullViolation | ErrorKind::CheckViolation => {
+                    ApiError::Unprocessable("The request violates a data constraint.".into())
+                }
+                // insufficient_privilege, raised when row level security
+                // rejects a write the handlers did not catch.
+                _ if db.code().as_deref() == Some("42501") => {
+                    ApiError::Forbidden("The request is not allowed.".into())
+                }
+                // serialization_failure and deadlock_detected, when the
+                // request raced a concurrent change and lost.
+                _ if matches!(db.code().as_deref(), Some("40001" | "40P01")) => {
+                    ApiError::Conflict("The request conflicted with a concurrent change.".into())
+                }
+                // Class 22: data exceptions such as values out of range.
+                _ if db.code().is_some_and(|code| code.starts_with("22")) => {
+                    ApiError::Unprocessable("The request contains an invalid value.".into())
+                }
+                _ => ApiError::Internal(error),
+            },
+            _ => ApiError::Internal(error),
+        }
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        ApiError::Rejected(rejection.status(), rejection.body_text())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        match &self {
+            ApiError::Internal(error) => tracing::error!("Unhandled error: {:?}", error),
+            ApiError::Unavailable(error) => tracing::warn!("Database unavailable: {:?}", error),
+            _ => tracing::debug!("Request failed: {}", self),
+        }
+
+        let mut response = (
+            self.status(),
+            [(header::CONTENT_TYPE, "application/problem+json")],
+            Json(self.problem()),
+        )
+            .into_response();
+        if response.status() == StatusCode::UNAUTHORIZED {
+            response
+                .headers_mut()
+                .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        }
+        response
+    }
+}
@@ -0,0 +1,316 @@
+//! Entity tags for optimistic concurrency control and conditional requests.
+//!
+//! Todos carry a `version` that is bumped on every change to what they look
+//! like, including their tags and the progress of their subtasks. It is
+//! sent as a strong `ETag`, and a client that sends it back in `If-Match`
+//! only has its change applied if nobody else changed the todo in between;
+//! otherwise it gets 412 Precondition Failed.
+//!
+//! Reads also answer `If-None-Match` and `If-Modified-Since` with 304 Not
+//! Modified when the client's copy is still current. Representations without
+//! a version of their own, such as pages of todos, get a weak tag derived
+//! from their body and no `Last-Modified`, so they are only revalidated by
+//! their tag.
+
+use axum::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::http::{header, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, SubsecRound, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::error::ApiError;
+
+/// The entity tag of a resource at `version`.
+pub fn from_version(version: i32) -> String {
+    format!("\"{version}\"")
+}
+
+/// A weak entity tag for `body`, from a hash of its JSON form.
+pub fn from_body<T: Serialize>(body: &T) -> String {
+    let json = serde_json::to_vec(body).expect("response body serializes to JSON");
+    let digest = Sha256::digest(json);
+    format!("W/\"{}\"", URL_SAFE_NO_PAD.encode(&digest[..16]))
+}
+
+/// A JSON response carrying the entity tag of its body.
+#[derive(Debug)]
+pub struct Tagged<T> {
+    pub etag: String,
+    pub body: T,
+}
+
+impl<T: Serialize> IntoResponse for Tagged<T> {
+    fn into_response(self) -> Response {
+        let mut response = Json(self.body).into_response();
+        if let Ok(etag) = HeaderValue::from_str(&self.etag) {
+            response.headers_mut().insert(header::ETAG, etag);
+        }
+        response
+    }
+}
+
+/// The `If-Match` request header, if present.
+#[derive(Debug)]
+pub struct IfMatch(Option<String>);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for IfMatch {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let value = parts
+            .headers
+            .get_all(header::IF_MATCH)
+            .iter()
+            .map(|value| {
+                value.to_str().map_err(|_| {
+                    ApiError::Rejected(
+                        StatusCode::BAD_REQUEST,
+                        "The If-Match header is not valid.".into(),
+                    )
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(IfMatch((!value.is_empty()).then(|| value.join(","))))
+    }
+}
+
+impl IfMatch {
+    /// A precondition on `version`, for changes that do not come with
+    /// headers of their own.
+    pub fn version(version: Option<i32>) -> Self {
+        IfMatch(version.map(from_version))
+    }
+
+    /// Check the current entity tag of the resource against the header,
+    /// using the strong comparison: weak tags never match.
+    pub fn check(&self, etag: &str) -> Result<(), ApiError> {
+        let Some(header) = &self.0 else {
+            return Ok(());
+        };
+        let matches = header
+            .split(',')
+            .map(str::trim)
+            .any(|tag| tag == "*" || tag == etag);
+        if matches {
+            Ok(())
+        } else {
+            Err(ApiError::PreconditionFailed)
+        }
+    }
+}
+
+/// Format a time as an HTTP date, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+fn http_date(time: DateTime<Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// What a client can revalidate a representation against.
+#[derive(Debug)]
+pub struct Validators {
+    pub etag: String,
+    pub last_modified: Option<DateTime<Utc>>,
+}
+
+impl Validators {
+    /// Add the validators and the `Cache-Control` policy to `response`.
+    pub fn apply(&self, response: impl IntoResponse, cache_control: &str) -> Response {
+        let mut response = response.into_response();
+        let headers = response.headers_mut();
+        if let Ok(etag) = HeaderValue::from_str(&self.etag) {
+            headers.insert(header::ETAG, etag);
+        }
+        if let Some(last_modified) = self.last_modified {
+            if let Ok(last_modified) = HeaderValue::from_str(&http_date(last_modified)) {
+                headers.insert(header::LAST_MODIFIED, last_modified);
+            }
+        }
+        if let Ok(cache_control) = HeaderValue::from_str(cache_control) {
+            headers.insert(header::CACHE_CONTROL, cache_control);
+        }
+        // Every representation depends on who is asking.
+        headers.insert(header::VARY, HeaderValue::from_static("authorization"));
+        response
+    }
+
+    pub fn not_modified(&self, cache_control: &str) -> Response {
+        self.apply(StatusCode::NOT_MODIFIED, cache_control)
+    }
+}
+
+/// The `If-None-Match` and `If-Modified-Since` request headers, if present.
+#[derive(Debug)]
+pub struct Conditional {
+    if_none_match: Option<String>,
+    if_modified_since: Option<DateTime<Utc>>,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Conditional {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let if_none_match = parts
+            .headers
+            .get_all(header::IF_NONE_MATCH)
+            .iter()
+            .map(|value| {
+                value.to_str().map_err(|_| {
+                    ApiError::Rejected(
+                        StatusCode::BAD_REQUEST,
+                        "The If-None-Match header is not valid.".into(),
+                    )
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        // An invalid date is ignored rather than rejected, as RFC 9110 asks.
+        let if_modified_since = parts
+            .headers
+            .get(header::IF_MODIFIED_SINCE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| DateTime::parse_from_rfc2822(value).ok())
+            .map(|time| time.with_timezone(&Utc));
+        Ok(Conditional {
+            if_none_match: (!if_none_match.is_empty()).then(|| if_none_match.join(",")),
+            if_modified_since,
+        })
+    }
+}
+
+impl Conditional {
+    /// Whether the client's copy is still current. `If-None-Match` uses the
+    /// weak comparison and takes precedence over `If-Modified-Since`, which
+    /// is only as precise as a second.
+    pub fn is_current(&self, validators: &Validators) -> bool {
+        if let Some(header) = &self.if_none_match {
+            let opaque = |tag: &str| tag.strip_prefix("W/").unwrap_or(tag).to_string();
+            let etag = opaque(&validators.etag);
+            return header
+                .split(',')
+                .map(str::trim)
+                .any(|tag| tag == "*" || opaque(tag) == etag);
+        }
+        match (self.if_modified_since, validators.last_modified) {
+            (Some(since), Some(last_modified)) => last_modified.trunc_subsecs(0) <= since,
+            _ => false,
+        }
+    }
+
+    /// Answer with `body`, tagged by its contents, or with 304 Not Modified
+    /// if the client's copy is still current.
+    pub fn respond<T: Serialize + IntoResponse>(&self, body: T, cache_control: &str) -> Response {
+        let validators = Validators {
+            etag: from_body(&body),
+            last_modified: None,
+        };
+        if self.is_current(&validators) {
+            return validators.not_modified(cache_control);
+        }
+        validators.apply(body, cache_control)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn if_match(header: &str) -> IfMatch {
+        IfMatch(Some(header.to_string()))
+    }
+
+    fn conditional(
+        if_none_match: Option<&str>,
+        if_modified_since: Option<DateTime<Utc>>,
+    ) -> Conditional {
+        Conditional {
+            if_none_match: if_none_match.map(str::to_string),
+            if_modified_since,
+        }
+    }
+
+    fn at(secs: i64, millis: u32) -> DateTime<Utc> {
+        Utc.timestamp_opt(secs, millis * 1_000_000).unwrap()
+    }
+
+    #[test]
+    fn if_match_without_header_passes() {
+        assert!(IfMatch(None).check("\"3\"").is_ok());
+        assert!(IfMatch::version(None).check("\"3\"").is_ok());
+    }
+
+    #[test]
+    fn if_match_compares_strongly() {
+        assert!(if_match("\"3\"").check("\"3\"").is_ok());
+        assert!(IfMatch::version(Some(3)).check(&from_version(3)).is_ok());
+        assert!(matches!(
+            if_match("\"2\"").check("\"3\""),
+            Err(ApiError::PreconditionFailed)
+        ));
+        assert!(if_match("W/\"3\"").check("\"3\"").is_err());
+    }
+
+    #[test]
+    fn if_match_accepts_any_tag_of_a_list_or_a_star() {
+        assert!(if_match("\"1\", \"3\" ,\"5\"").check("\"3\"").is_ok());
+        assert!(if_match("\"1\",\"5\"").check("\"3\"").is_err());
+        assert!(if_match("*").check("\"3\"").is_ok());
+    }
+
+    #[test]
+    fn if_none_match_compares_weakly() {
+        let validators = Validators {
+            etag: "\"3\"".into(),
+            last_modified: None,
+        };
+        assert!(conditional(Some("\"3\""), None).is_current(&validators));
+        assert!(conditional(Some("W/\"3\""), None).is_current(&validators));
+        assert!(conditional(Some("\"1\", W/\"3\""), None).is_current(&validators));
+        assert!(conditional(Some("*"), None).is_current(&validators));
+        assert!(!conditional(Some("\"4\""), None).is_current(&validators));
+
+        let weak = Validators {
+            etag: "W/\"abc\"".into(),
+            last_modified: None,
+        };
+        assert!(conditional(Some("\"abc\""), None).is_current(&weak));
+    }
+
+    #[test]
+    fn if_none_match_takes_precedence_over_if_modified_since() {
+        let validators = Validators {
+            etag: "\"3\"".into(),
+            last_modified: Some(at(1_000, 0)),
+        };
+        assert!(!conditional(Some("\"2\""), Some(at(2_000, 0))).is_current(&validators));
+        assert!(conditional(Some("\"3\""), Some(at(0, 0))).is_current(&validators));
+    }
+
+    #[test]
+    fn if_modified_since_ignores_fractions_of_a_second() {
+        let validators = Validators {
+            etag: "\"3\"".into(),
+            last_modified: Some(at(1_000, 750)),
+        };
+        assert!(conditional(None, Some(at(1_000, 0))).is_current(&validators));
+        assert!(conditional(None, Some(at(1_001, 0))).is_current(&validators));
+        assert!(!conditional(None, Some(at(999, 0))).is_current(&validators));
+    }
+
+    #[test]
+    fn without_last_modified_nothing_is_current() {
+        let validators = Validators {
+            etag: "W/\"abc\"".into(),
+            last_modified: None,
+        };
+        assert!(!conditional(None, Some(at(1_000, 0))).is_current(&validators));
+        assert!(!conditional(None, None).is_current(&validators));
+    }
+}
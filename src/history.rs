@@ -0,0 +1,217 @@
+//! The change history of todos.
+//!
+//! Every create, update, completion and delete of a todo is recorded by a
+//! trigger, see migration 0013, together with the user and API key that
+//! made it. Entries are visible to whoever can currently see the list the
+//! todo was in at the time. Tags live in their own table, so changes to the
+//! tags of a todo are recorded by [`record_tags`] instead, and renaming or
+//! deleting a tag by [`record_tag_change`] for every todo that carries it.
+
+use axum::extract::{OriginalUri, Path, State};
+use axum::routing::get;
+use axum::{Json, Router};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::{PgConnection, PgPool};
+
+use crate::auth::CurrentUser;
+use crate::db;
+use crate::error::ApiError;
+use crate::pagination::{self, Cursor, Page};
+use crate::validation::{ValidQuery, Validate, ValidationErrors};
+use crate::AppState;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/todos/:id/history", get(get_todo_history))
+        .route("/audit", get(get_audit))
+}
+
+/// Record that the tags of a todo changed from `before` to `after`, as an
+/// update of its `tags` field made by the user of the transaction.
+pub async fn record_tags(
+    conn: &mut PgConnection,
+    todo_id: i32,
+    before: &[String],
+    after: &[String],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO todo_history (todo_id, list_id, actor_id, api_key_id, action, before, after)
+        SELECT id, list_id, app_user_id(),
+            NULLIF(current_setting('app.api_key_id', true), '')::integer, 'update',
+            jsonb_build_object('tags', $2::text[]), jsonb_build_object('tags', $3::text[])
+        FROM todos WHERE id = $1
+        "#,
+        todo_id,
+        before,
+        after
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Record that the tag `tag_id` is about to be renamed to `name`, or
+/// deleted if `name` is `None`, as an update of the `tags` field of every
+/// todo carrying it. Call it before the change, while the tag is still
+/// attached. Nothing is recorded if the name stays the same.
+pub async fn record_tag_change(
+    conn: &mut PgConnection,
+    tag_id: i32,
+    name: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO todo_history (todo_id, list_id, actor_id, api_key_id, action, before, after)
+        SELECT todos.id, todos.list_id, app_user_id(),
+            NULLIF(current_setting('app.api_key_id', true), '')::integer, 'update',
+            jsonb_build_object('tags', tagged.before), jsonb_build_object('tags', tagged.after)
+        FROM (
+            SELECT tt.todo_id,
+                array_agg(t.name ORDER BY lower(t.name)) AS before,
+                COALESCE(
+                    array_agg(changed.name ORDER BY lower(changed.name))
+                        FILTER (WHERE changed.name IS NOT NULL),
+                    '{}'
+                ) AS after
+            FROM todo_tags tt JOIN tags t ON t.id = tt.tag_id
+            CROSS JOIN LATERAL (
+                SELECT CASE WHEN t.id = $1 THEN $2::text ELSE t.name END AS name
+            ) changed
+            WHERE tt.todo_id IN (SELECT todo_id FROM todo_tags WHERE tag_id = $1)
+            GROUP BY tt.todo_id
+        ) tagged
+        JOIN todos ON todos.id = tagged.todo_id
+        WHERE tagged.before IS DISTINCT FROM tagged.after
+        "#,
+        tag_id,
+        name
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+struct Entry {
+    id: i64,
+    todo_id: i32,
+    list_id: i32,
+    /// The user who made the change, `null` for changes the server made on
+    /// its own.
+    actor_id: Option<i32>,
+    actor_email: Option<String>,
+    api_key_id: Option<i32>,
+    /// One of `create`, `update`, `complete`, `delete`, `restore` or `purge`.
+    action: String,
+    before: Option<Value>,
+    after: Option<Value>,
+    created_at: DateTime<Utc>,
+    /// The transaction that made the change, which orders the audit log.
+    #[serde(skip)]
+    xact_id: i64,
+}
+
+/// Every change to a todo, oldest first.
+async fn get_todo_history(
+    State(pool): State<PgPool>,
+    user: CurrentUser,
+    Path(id): Path<i32>,
+) -> Result<Json<Vec<Entry>>, ApiError> {
+    let mut tx = db::begin(&pool, user).await?;
+    let entries = sqlx::query_as!(
+        Entry,
+        r#"
+        SELECT h.id, h.todo_id, h.list_id, h.actor_id, u.email AS "actor_email?",
+            h.api_key_id, h.action, h.before, h.after, h.created_at, h.xact_id
+        FROM todo_history h LEFT JOIN users u ON u.id = h.actor_id
+        WHERE h.todo_id = $1
+            AND h.list_id IN (SELECT id FROM lists_with_role($2, 'viewer'))
+        ORDER BY h.id
+        "#,
+        id,
+        user.id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    if entries.is_empty() {
+        return Err(ApiError::NotFound);
+    }
+
+    Ok(Json(entries))
+}
+
+#[derive(Debug, Deserialize)]
+struct AuditQuery {
+    /// Only changes made at or after this time.
+    since: DateTime<Utc>,
+    after: Option<String>,
+    limit: Option<u32>,
+}
+
+impl Validate for AuditQuery {
+    fn validate(&mut self, errors: &mut ValidationErrors) {
+        if self.limit == Some(0) {
+            errors.add("limit", "out_of_range", "must be at least 1");
+        }
+    }
+}
+
+/// The changes to every todo the caller can see, in keyset pages ordered
+/// by transaction. A change only shows up once every transaction that
+/// started before it has finished, so that no page is followed by changes
+/// that belong before it.
+async fn get_audit(
+    State(state): State<AppState>,
+    user: CurrentUser,
+    OriginalUri(uri): OriginalUri,
+    ValidQuery(query): ValidQuery<AuditQuery>,
+) -> Result<Page<Entry>, ApiError> {
+    let limit = pagination::limit(query.limit, &state.config.pagination);
+    let after = match &query.after {
+        Some(after) => Cursor::decode(after, "xact_id,id")?
+            .map(|cursor| Ok::<_, ApiError>((cursor.key::<i64>(0)?, cursor.key::<i64>(1)?)))
+            .transpose()?,
+        None => None,
+    };
+    let (after_xact_id, after_id) = after.unwrap_or((0, 0));
+
+    let mut tx = db::begin(&state.pool, user).await?;
+    // Fetch one extra row to learn whether another page follows.
+    let mut entries = sqlx::query_as!(
+        Entry,
+        r#"
+        SELECT h.id, h.todo_id, h.list_id, h.actor_id, u.email AS "actor_email?",
+            h.api_key_id, h.action, h.before, h.after, h.created_at, h.xact_id
+        FROM todo_history h LEFT JOIN users u ON u.id = h.actor_id
+        WHERE h.created_at >= $1
+            AND (h.xact_id, h.id) > ($2, $3)
+            AND h.xact_id < pg_snapshot_xmin(pg_current_snapshot())::text::bigint
+            AND h.list_id IN (SELECT id FROM lists_with_role($4, 'viewer'))
+        ORDER BY h.xact_id, h.id
+        LIMIT $5
+        "#,
+        query.since,
+        after_xact_id,
+        after_id,
+        user.id,
+        limit + 1
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    let next = if entries.len() as i64 > limit {
+        entries.truncate(limit as usize);
+        entries
+            .last()
+            .map(|entry| Cursor::new("xact_id,id", vec![json!(entry.xact_id), json!(entry.id)]))
+    } else {
+        None
+    };
+
+    Ok(Page::keyset(entries, limit, next, &uri))
+}
@@ -0,0 +1,245 @@
+//! Safe retries of mutating requests.
+//!
+//! A POST, PUT, PATCH or DELETE sent with an `Idempotency-Key` header has
+//! its response stored under that key, and a retry with the same key gets
+//! the stored response, marked with `Idempotent-Replayed: true`, instead of
+//! making the change again. Keys belong to a user and are remembered for
+//! `idempotency.window_secs`. Reusing a key for a different request is
+//! rejected with 422, retrying while the first request is still running
+//! with 409. A request that has not finished after `idempotency.lease_secs`
+//! loses its claim to the next retry. Server errors are not stored, so those
+//! requests can be retried. New API keys are never stored.
+
+use axum::body::{to_bytes, Body};
+use axum::extract::{Request, State};
+use axum::http::{HeaderName, HeaderValue, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::Response;
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use sqlx::types::Json;
+use sqlx::PgPool;
+
+use crate::auth::CurrentUser;
+use crate::config::IdempotencyConfig;
+use crate::error::ApiError;
+use crate::AppState;
+
+const HEADER: &str = "idempotency-key";
+
+const REPLAYED: &str = "idempotent-replayed";
+
+const MAX_KEY_LEN: usize = 255;
+
+/// The largest request body that is fingerprinted, the same limit axum
+/// applies to JSON bodies.
+const MAX_BODY: usize = 2 * 1024 * 1024;
+
+/// Forget every key older than the window, returning how many were removed.
+pub async fn purge_expired(pool: &PgPool, config: &IdempotencyConfig) -> Result<u64, sqlx::Error> {
+    let purged = sqlx::query!(
+        "DELETE FROM idempotency_keys WHERE created_at < now() - make_interval(secs => $1)",
+        config.window_secs as f64
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+    Ok(purged)
+}
+
+/// A hash of what makes two requests the same.
+fn fingerprint(method: &Method, uri: &str, body: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(method.as_str());
+    hasher.update(b"\n");
+    hasher.update(uri);
+    hasher.update(b"\n");
+    hasher.update(body);
+    hasher.finalize().to_vec()
+}
+
+/// Answer a retried request with the stored response, or run it and store
+/// its response. Runs after authentication, which puts the caller in the
+/// request extensions.
+pub async fn middleware(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    if matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    ) {
+        return Ok(next.run(request).await);
+    }
+    let Some(key) = request.headers().get(HEADER) else {
+        return Ok(next.run(request).await);
+    };
+    let key = key
+        .to_str()
+        .ok()
+        .filter(|key| !key.is_empty() && key.len() <= MAX_KEY_LEN)
+        .ok_or_else(|| {
+            ApiError::Rejected(
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "The Idempotency-Key header must be 1 to {MAX_KEY_LEN} visible characters."
+                ),
+            )
+        })?
+        .to_string();
+    let Some(user) = request.extensions().get::<CurrentUser>().copied() else {
+        return Ok(next.run(request).await);
+    };
+
+    let (parts, body) = request.into_parts();
+    let body = to_bytes(body, MAX_BODY).await.map_err(|_| {
+        ApiError::Rejected(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "The request body is too large.".into(),
+        )
+    })?;
+    let fingerprint = fingerprint(&parts.method, &parts.uri.to_string(), &body);
+    let request = Request::from_parts(parts, Body::from(body));
+
+    // Claim the key, taking it over if it has expired but not been purged or
+    // if the request holding it has outlived its lease.
+    let config = &state.config.idempotency;
+    let claimed_at = sqlx::query_scalar!(
+        r#"
+        INSERT INTO idempotency_keys (user_id, key, fingerprint, locked_until)
+        VALUES ($1, $2, $3, now() + make_interval(secs => $5))
+        ON CONFLICT (user_id, key) DO UPDATE
+        SET fingerprint = EXCLUDED.fingerprint, status = NULL, headers = NULL, body = NULL,
+            created_at = now(), locked_until = EXCLUDED.locked_until
+        WHERE idempotency_keys.created_at < now() - make_interval(secs => $4)
+            OR idempotency_keys.locked_until < now()
+        RETURNING created_at
+        "#,
+        user.id,
+        key,
+        fingerprint,
+        config.window_secs as f64,
+        config.lease_secs as f64
+    )
+    .fetch_optional(&state.pool)
+    .await?;
+    let Some(claimed_at) = claimed_at else {
+        return replay(&state.pool, user, &key, &fingerprint).await;
+    };
+
+    let response = next.run(request).await;
+    if response.status().is_server_error() {
+        release(&state.pool, user, &key, claimed_at).await;
+        return Ok(response);
+    }
+    match store(&state.pool, user, &key, claimed_at, response).await {
+        Ok(response) => Ok(response),
+        Err(err) => {
+            release(&state.pool, user, &key, claimed_at).await;
+            Err(err)
+        }
+    }
+}
+
+/// Store the response to a claimed key. Nothing is stored if a retry has
+/// taken the key over in the meantime.
+async fn store(
+    pool: &PgPool,
+    user: CurrentUser,
+    key: &str,
+    claimed_at: DateTime<Utc>,
+    response: Response,
+) -> Result<Response, ApiError> {
+    let (parts, body) = response.into_parts();
+    let body = to_bytes(body, usize::MAX).await.map_err(|err| {
+        tracing::error!("Failed to buffer a response: {}", err);
+        ApiError::Rejected(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "An unexpected error occurred.".into(),
+        )
+    })?;
+    let headers: Vec<(String, String)> = parts
+        .headers
+        .iter()
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect();
+    sqlx::query!(
+        r#"
+        UPDATE idempotency_keys SET status = $4, headers = $5, body = $6, locked_until = NULL
+        WHERE user_id = $1 AND key = $2 AND created_at = $3
+        "#,
+        user.id,
+        key,
+        claimed_at,
+        parts.status.as_u16() as i16,
+        Json(&headers) as _,
+        &body[..]
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(Response::from_parts(parts, Body::from(body)))
+}
+
+/// Give up a claimed key without a stored response, so that the request can
+/// be retried. A claim that cannot be released runs out with its lease.
+async fn release(pool: &PgPool, user: CurrentUser, key: &str, claimed_at: DateTime<Utc>) {
+    let released = sqlx::query!(
+        "DELETE FROM idempotency_keys WHERE user_id = $1 AND key = $2 AND created_at = $3",
+        user.id,
+        key,
+        claimed_at
+    )
+    .execute(pool)
+    .await;
+    if let Err(err) = released {
+        tracing::warn!("Failed to release an idempotency key: {}", err);
+    }
+}
+
+/// Answer a request whose key is already taken.
+async fn replay(
+    pool: &PgPool,
+    user: CurrentUser,
+    key: &str,
+    fingerprint: &[u8],
+) -> Result<Response, ApiError> {
+    let stored = sqlx::query!(
+        r#"
+        SELECT fingerprint, status, headers AS "headers: Json<Vec<(String, String)>>", body
+        FROM idempotency_keys
+        WHERE user_id = $1 AND key = $2
+        "#,
+        user.id,
+        key
+    )
+    .fetch_one(pool)
+    .await?;
+    if stored.fingerprint != fingerprint {
+        return Err(ApiError::Unprocessable(
+            "The Idempotency-Key was already used for a different request.".into(),
+        ));
+    }
+    let (Some(status), Some(Json(headers)), Some(body)) =
+        (stored.status, stored.headers, stored.body)
+    else {
+        return Err(ApiError::Conflict(
+            "A request with this Idempotency-Key is still being processed.".into(),
+        ));
+    };
+
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = StatusCode::from_u16(status as u16).unwrap_or(StatusCode::OK);
+    let response_headers = response.headers_mut();
+    for (name, value) in headers {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            response_headers.append(name, value);
+        }
+    }
+    response_headers.insert(REPLAYED, HeaderValue::from_static("true"));
+    Ok(response)
+}
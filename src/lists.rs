@@ -0,0 +1,325 @@
+//! Todo lists, also known as projects.
+//!
+//! Every todo belongs to exactly one list. Each user has an inbox, created
+//! when they register, that receives todos created without a list and can
+//! be neither archived nor deleted. The todos of a list are served by
+//! [`crate::todos`] under `/lists/:id/todos`.
+//!
+//! Other lists can be shared with other users, who then act on them with
+//! the [`Role`] given to them, see [`members`].
+
+use axum::extract::{Path, State};
+use axum::routing::get;
+use axum::{Json, Router};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+
+use crate::auth::CurrentUser;
+use crate::db;
+use crate::error::ApiError;
+use crate::patch::Patch;
+use crate::validation::{self, TextRules, ValidJson, ValidQuery, Validate, ValidationErrors};
+use crate::AppState;
+
+pub use self::members::Role;
+
+mod members;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .merge(members::router())
+        .route("/lists", get(get_lists).post(add_list))
+        .route(
+            "/lists/:id",
+            get(get_list).patch(patch_list).delete(delete_list),
+        )
+}
+
+#[derive(Debug, Serialize)]
+pub struct List {
+    id: i32,
+    name: String,
+    description: Option<String>,
+    position: i32,
+    archived: bool,
+    inbox: bool,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    todo_count: i64,
+    completed_count: i64,
+    /// What the caller may do with the list.
+    role: Role,
+}
+
+const NAME: TextRules = TextRules {
+    min_len: 1,
+    max_len: 100,
+    allow_newlines: false,
+};
+
+const DESCRIPTION: TextRules = TextRules {
+    min_len: 0,
+    max_len: 2000,
+    allow_newlines: true,
+};
+
+/// The role of `user` on the list `id`, or `NotFound` if they cannot see it.
+pub async fn role(conn: &mut PgConnection, user: CurrentUser, id: i32) -> Result<Role, ApiError> {
+    sqlx::query_scalar!(r#"SELECT list_role($1, $2) AS "role: Role""#, id, user.id)
+        .fetch_one(conn)
+        .await?
+        .ok_or(ApiError::NotFound)
+}
+
+/// Resolve the list a todo of `user` goes into, defaulting to their inbox,
+/// and check that it accepts todos from them.
+pub async fn resolve(
+    conn: &mut PgConnection,
+    user: CurrentUser,
+    list_id: Option<i32>,
+) -> Result<i32, ApiError> {
+    let list = sqlx::query!(
+        r#"
+        SELECT id, archived, list_role(id, $2) AS "role!: Role" FROM lists
+        WHERE (id = $1 OR ($1 IS NULL AND inbox AND owner_id = $2))
+            AND list_role(id, $2) IS NOT NULL
+        "#,
+        list_id,
+        user.id
+    )
+    .fetch_optional(conn)
+    .await?;
+    match list {
+        None => Err(ApiError::Unprocessable("The list does not exist.".into())),
+        Some(list) if list.archived => Err(ApiError::Conflict("The list is archived.".into())),
+        Some(list) => {
+            list.role.require(Role::Editor)?;
+            Ok(list.id)
+        }
+    }
+}
+
+/// Create the inbox of a newly registered user.
+pub async fn create_inbox(conn: &mut PgConnection, user: CurrentUser) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO lists (owner_id, name, position, inbox) VALUES ($1, 'Inbox', 0, true)",
+        user.id
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+async fn fetch_list(
+    conn: &mut PgConnection,
+    user: CurrentUser,
+    id: i32,
+) -> Result<List, sqlx::Error> {
+    sqlx::query_as!(
+        List,
+        r#"
+        SELECT id, name, description, position, archived, inbox, created_at, updated_at,
+            (SELECT COUNT(*) FROM todos WHERE list_id = lists.id AND deleted_at IS NULL)
+                AS "todo_count!",
+            (SELECT COUNT(*) FROM todos
+                WHERE list_id = lists.id AND deleted_at IS NULL AND completed)
+                AS "completed_count!",
+            list_role(id, $2) AS "role!: Role"
+        FROM lists
+        WHERE id = $1 AND list_role(id, $2) IS NOT NULL
+        "#,
+        id,
+        user.id
+    )
+    .fetch_one(conn)
+    .await
+}
+
+#[derive(Debug, Deserialize)]
+struct ListsQuery {
+    /// Show the archived lists instead of the active ones.
+    #[serde(default)]
+    archived: bool,
+}
+
+impl Validate for ListsQuery {
+    fn validate(&mut self, _errors: &mut ValidationErrors) {}
+}
+
+async fn get_lists(
+    State(pool): State<PgPool>,
+    user: CurrentUser,
+    ValidQuery(query): ValidQuery<ListsQuery>,
+) -> Result<Json<Vec<List>>, ApiError> {
+    let mut tx = db::begin(&pool, user).await?;
+    let lists = sqlx::query_as!(
+        List,
+        r#"
+        SELECT id, name, description, position, archived, inbox, created_at, updated_at,
+            (SELECT COUNT(*) FROM todos WHERE list_id = lists.id AND deleted_at IS NULL)
+                AS "todo_count!",
+            (SELECT COUNT(*) FROM todos
+                WHERE list_id = lists.id AND deleted_at IS NULL AND completed)
+                AS "completed_count!",
+            list_role(id, $1) AS "role!: Role"
+        FROM lists
+        WHERE list_role(id, $1) IS NOT NULL AND archived = $2
+        ORDER BY position, id
+        "#,
+        user.id,
+        query.archived
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(Json(lists))
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateList {
+    name: String,
+    description: Option<String>,
+    /// Defaults to after the last list.
+    position: Option<i32>,
+}
+
+impl Validate for CreateList {
+    fn validate(&mut self, errors: &mut ValidationErrors) {
+        validation::text(errors, "name", &mut self.name, &NAME);
+        if let Some(description) = &mut self.description {
+            validation::text(errors, "description", description, &DESCRIPTION);
+        }
+    }
+}
+
+async fn add_list(
+    State(pool): State<PgPool>,
+    user: CurrentUser,
+    ValidJson(input): ValidJson<CreateList>,
+) -> Result<Json<List>, ApiError> {
+    // The new list would be outside the restriction of the key.
+    user.require_unrestricted()?;
+    let mut tx = db::begin(&pool, user).await?;
+    let id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO lists (owner_id, name, description, position)
+        VALUES (
+            $1, $2, $3,
+            COALESCE($4, (SELECT COALESCE(MAX(position), 0) + 1 FROM lists WHERE owner_id = $1))
+        )
+        RETURNING id
+        "#,
+        user.id,
+        input.name,
+        input.description,
+        input.position
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    let list = fetch_list(&mut tx, user, id).await?;
+    tx.commit().await?;
+
+    Ok(Json(list))
+}
+
+async fn get_list(
+    State(pool): State<PgPool>,
+    user: CurrentUser,
+    Path(id): Path<i32>,
+) -> Result<Json<List>, ApiError> {
+    let mut tx = db::begin(&pool, user).await?;
+    let list = fetch_list(&mut tx, user, id).await?;
+    tx.commit().await?;
+
+    Ok(Json(list))
+}
+
+/// A JSON Merge Patch document for a list.
+#[derive(Debug, Deserialize)]
+struct PatchList {
+    #[serde(default)]
+    name: Patch<String>,
+    #[serde(default)]
+    description: Patch<String>,
+    #[serde(default)]
+    position: Patch<i32>,
+    #[serde(default)]
+    archived: Patch<bool>,
+}
+
+impl Validate for PatchList {
+    fn validate(&mut self, errors: &mut ValidationErrors) {
+        validation::required(errors, "name", &mut self.name, |errors, value| {
+            validation::text(errors, "name", value, &NAME)
+        });
+        if let Patch::Value(description) = &mut self.description {
+            validation::text(errors, "description", description, &DESCRIPTION);
+        }
+        validation::required(errors, "position", &mut self.position, |_, _| {});
+        validation::required(errors, "archived", &mut self.archived, |_, _| {});
+    }
+}
+
+async fn patch_list(
+    State(pool): State<PgPool>,
+    user: CurrentUser,
+    Path(id): Path<i32>,
+    ValidJson(patch): ValidJson<PatchList>,
+) -> Result<Json<List>, ApiError> {
+    let mut tx = db::begin(&pool, user).await?;
+    let list = fetch_list(&mut tx, user, id).await?;
+    list.role.require(Role::Admin)?;
+    if list.inbox && patch.archived == Patch::Value(true) {
+        return Err(ApiError::Conflict("The inbox cannot be archived.".into()));
+    }
+    sqlx::query!(
+        r#"
+        UPDATE lists
+        SET name = COALESCE($1, name),
+            description = CASE WHEN $2 THEN $3 ELSE description END,
+            position = COALESCE($4, position),
+            archived = COALESCE($5, archived),
+            updated_at = now()
+        WHERE id = $6 AND list_role(id, $7) >= 'admin'
+        "#,
+        patch.name.value(),
+        !patch.description.is_absent(),
+        patch.description.value(),
+        patch.position.value(),
+        patch.archived.value(),
+        id,
+        user.id
+    )
+    .execute(&mut *tx)
+    .await?;
+    let list = fetch_list(&mut tx, user, id).await?;
+    tx.commit().await?;
+
+    Ok(Json(list))
+}
+
+/// Delete a list together with its todos.
+async fn delete_list(
+    State(pool): State<PgPool>,
+    user: CurrentUser,
+    Path(id): Path<i32>,
+) -> Result<Json<List>, ApiError> {
+    let mut tx = db::begin(&pool, user).await?;
+    let list = fetch_list(&mut tx, user, id).await?;
+    list.role.require(Role::Owner)?;
+    if list.inbox {
+        return Err(ApiError::Conflict("The inbox cannot be deleted.".into()));
+    }
+    sqlx::query!(
+        "DELETE FROM lists WHERE id = $1 AND owner_id = $2",
+        id,
+        user.id
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(Json(list))
+}
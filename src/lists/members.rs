@@ -0,0 +1,249 @@
+//! Sharing lists with other users.
+//!
+//! The owner of a list can make other users members of it, each with a
+//! role: viewers see the list and its todos, editors also change the todos
+//! and admins also change the list and its members. Only the owner can
+//! delete the list. Inboxes cannot be shared.
+
+use axum::extract::{Path, State};
+use axum::routing::{get, patch};
+use axum::{Json, Router};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+
+use crate::auth::CurrentUser;
+use crate::db;
+use crate::error::ApiError;
+use crate::patch::Patch;
+use crate::validation::{self, ValidJson, Validate, ValidationErrors};
+use crate::AppState;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/lists/:id/members", get(get_members).post(add_member))
+        .route(
+            "/lists/:id/members/:user_id",
+            patch(patch_member).delete(delete_member),
+        )
+}
+
+/// What a user may do with a list, each role allowing everything the ones
+/// before it do.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, sqlx::Type,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "list_role", rename_all = "lowercase")]
+pub enum Role {
+    Viewer,
+    Editor,
+    Admin,
+    /// Held by the owner of the list only, never given to members.
+    Owner,
+}
+
+impl Role {
+    fn as_str(self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Editor => "editor",
+            Role::Admin => "admin",
+            Role::Owner => "owner",
+        }
+    }
+
+    /// Check that the role allows what `needed` does.
+    pub fn require(self, needed: Role) -> Result<(), ApiError> {
+        if self >= needed {
+            Ok(())
+        } else {
+            Err(ApiError::Forbidden(format!(
+                "This requires the {} role on the list.",
+                needed.as_str()
+            )))
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Member {
+    user_id: i32,
+    email: String,
+    role: Role,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+fn validate_role(errors: &mut ValidationErrors, role: Role) {
+    if role == Role::Owner {
+        errors.add("role", "invalid_value", "must be viewer, editor or admin");
+    }
+}
+
+async fn fetch_member(
+    conn: &mut PgConnection,
+    list_id: i32,
+    user_id: i32,
+) -> Result<Member, sqlx::Error> {
+    sqlx::query_as!(
+        Member,
+        r#"
+        SELECT m.user_id, u.email, m.role AS "role: Role", m.created_at, m.updated_at
+        FROM list_members m JOIN users u ON u.id = m.user_id
+        WHERE m.list_id = $1 AND m.user_id = $2
+        "#,
+        list_id,
+        user_id
+    )
+    .fetch_one(conn)
+    .await
+}
+
+/// Everyone with access to the list, starting with its owner.
+async fn get_members(
+    State(pool): State<PgPool>,
+    user: CurrentUser,
+    Path(id): Path<i32>,
+) -> Result<Json<Vec<Member>>, ApiError> {
+    let mut tx = db::begin(&pool, user).await?;
+    super::role(&mut tx, user, id).await?;
+    let members = sqlx::query_as!(
+        Member,
+        r#"
+        SELECT u.id AS "user_id!", u.email AS "email!", 'owner'::list_role AS "role!: Role",
+            l.created_at AS "created_at!", l.created_at AS "updated_at!"
+        FROM lists l JOIN users u ON u.id = l.owner_id
+        WHERE l.id = $1
+        UNION ALL
+        SELECT m.user_id, u.email, m.role, m.created_at, m.updated_at
+        FROM list_members m JOIN users u ON u.id = m.user_id
+        WHERE m.list_id = $1
+        ORDER BY 3 DESC, 1
+        "#,
+        id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(Json(members))
+}
+
+#[derive(Debug, Deserialize)]
+struct AddMember {
+    /// The email address the user registered with.
+    email: String,
+    role: Role,
+}
+
+impl Validate for AddMember {
+    fn validate(&mut self, errors: &mut ValidationErrors) {
+        self.email = self.email.trim().to_string();
+        validate_role(errors, self.role);
+    }
+}
+
+async fn add_member(
+    State(pool): State<PgPool>,
+    user: CurrentUser,
+    Path(id): Path<i32>,
+    ValidJson(input): ValidJson<AddMember>,
+) -> Result<Json<Member>, ApiError> {
+    let mut tx = db::begin(&pool, user).await?;
+    super::role(&mut tx, user, id).await?.require(Role::Admin)?;
+    let list = sqlx::query!("SELECT owner_id, inbox FROM lists WHERE id = $1", id)
+        .fetch_one(&mut *tx)
+        .await?;
+    if list.inbox {
+        return Err(ApiError::Conflict("The inbox cannot be shared.".into()));
+    }
+    let member_id = sqlx::query_scalar!(
+        "SELECT id FROM users WHERE lower(email) = lower($1)",
+        input.email
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| ApiError::Unprocessable("No user has this email address.".into()))?;
+    if member_id == list.owner_id {
+        return Err(ApiError::Conflict("The user owns the list.".into()));
+    }
+    sqlx::query!(
+        "INSERT INTO list_members (list_id, user_id, role) VALUES ($1, $2, $3)",
+        id,
+        member_id,
+        input.role as Role
+    )
+    .execute(&mut *tx)
+    .await?;
+    let member = fetch_member(&mut tx, id, member_id).await?;
+    tx.commit().await?;
+
+    Ok(Json(member))
+}
+
+/// A JSON Merge Patch document for a membership.
+#[derive(Debug, Deserialize)]
+struct PatchMember {
+    #[serde(default)]
+    role: Patch<Role>,
+}
+
+impl Validate for PatchMember {
+    fn validate(&mut self, errors: &mut ValidationErrors) {
+        validation::required(errors, "role", &mut self.role, |errors, role| {
+            validate_role(errors, *role)
+        });
+    }
+}
+
+async fn patch_member(
+    State(pool): State<PgPool>,
+    user: CurrentUser,
+    Path((id, user_id)): Path<(i32, i32)>,
+    ValidJson(patch): ValidJson<PatchMember>,
+) -> Result<Json<Member>, ApiError> {
+    let mut tx = db::begin(&pool, user).await?;
+    super::role(&mut tx, user, id).await?.require(Role::Admin)?;
+    sqlx::query_scalar!(
+        r#"
+        UPDATE list_members
+        SET role = COALESCE($1, role), updated_at = now()
+        WHERE list_id = $2 AND user_id = $3
+        RETURNING user_id
+        "#,
+        patch.role.value() as Option<Role>,
+        id,
+        user_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    let member = fetch_member(&mut tx, id, user_id).await?;
+    tx.commit().await?;
+
+    Ok(Json(member))
+}
+
+/// Revoke a membership. Members can also remove themselves to leave a list.
+async fn delete_member(
+    State(pool): State<PgPool>,
+    user: CurrentUser,
+    Path((id, user_id)): Path<(i32, i32)>,
+) -> Result<Json<Member>, ApiError> {
+    let mut tx = db::begin(&pool, user).await?;
+    let role = super::role(&mut tx, user, id).await?;
+    if user_id != user.id {
+        role.require(Role::Admin)?;
+    }
+    let member = fetch_member(&mut tx, id, user_id).await?;
+    sqlx::query!(
+        "DELETE FROM list_members WHERE list_id = $1 AND user_id = $2",
+        id,
+        user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(Json(member))
+}
@@ -0,0 +1,75 @@
+//! Schema migrations, embedded from the `migrations/` directory at build time.
+
+use clap::Subcommand;
+use sqlx::migrate::{Migrate, MigrateError, Migrator};
+use sqlx::PgPool;
+
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+#[derive(Debug, Subcommand)]
+pub enum MigrateCommand {
+    /// Apply every pending migration.
+    Up,
+    /// Revert applied migrations, by default only the most recent one.
+    Down {
+        /// Revert down to (but not including) this version; 0 reverts everything.
+        #[arg(long)]
+        target: Option<i64>,
+    },
+    /// Show which migrations are applied and which are pending.
+    Status,
+}
+
+pub async fn run(pool: &PgPool, command: MigrateCommand) -> Result<(), MigrateError> {
+    match command {
+        MigrateCommand::Up => {
+            MIGRATOR.run(pool).await?;
+            println!("All migrations applied.");
+        }
+        MigrateCommand::Down { target } => {
+            let mut applied = applied_versions(pool).await?;
+            applied.sort_unstable();
+            let target = match target {
+                Some(target) => target,
+                None => match applied.as_slice() {
+                    [] => {
+                        println!("No migrations to revert.");
+                        return Ok(());
+                    }
+                    [.., previous, _] => *previous,
+                    [_] => 0,
+                },
+            };
+            MIGRATOR.undo(pool, target).await?;
+            println!("Reverted migrations newer than version {target}.");
+        }
+        MigrateCommand::Status => {
+            let mut conn = pool.acquire().await?;
+            conn.ensure_migrations_table().await?;
+            let applied = conn.list_applied_migrations().await?;
+
+            for migration in MIGRATOR
+                .iter()
+                .filter(|m| !m.migration_type.is_down_migration())
+            {
+                let status = match applied.iter().find(|a| a.version == migration.version) {
+                    Some(a) if a.checksum != migration.checksum => "applied (checksum mismatch)",
+                    Some(_) => "applied",
+                    None => "pending",
+                };
+                println!(
+                    "{:>6}  {:<28}  {}",
+                    migration.version, status, migration.description
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+pub async fn applied_versions(pool: &PgPool) -> Result<Vec<i64>, MigrateError> {
+    let mut conn = pool.acquire().await?;
+    conn.ensure_migrations_table().await?;
+    let applied = conn.list_applied_migrations().await?;
+    Ok(applied.into_iter().map(|m| m.version).collect())
+}
@@ -0,0 +1,243 @@
+//! Offset and keyset pagination for list endpoints.
+//!
+//! List handlers return a [`Page`], which serializes as an envelope with the
+//! items and `next`/`prev` links, and repeats those links in an RFC 8288
+//! `Link` header. Offset pages also carry the total count and their offset;
+//! keyset pages are addressed with an opaque [`Cursor`] instead.
+
+use axum::http::{header, HeaderValue, Uri};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+
+use crate::config::PaginationConfig;
+use crate::error::ApiError;
+use crate::validation::ValidationErrors;
+
+/// Apply the configured default and cap to the requested page size.
+pub fn limit(limit: Option<u32>, config: &PaginationConfig) -> i64 {
+    limit
+        .unwrap_or(config.default_limit)
+        .min(config.max_limit)
+        .into()
+}
+
+pub fn offset(offset: Option<u64>) -> i64 {
+    offset.unwrap_or(0).min(i64::MAX as u64) as i64
+}
+
+/// Deserialize an `after` parameter, keeping an empty value, which starts a
+/// keyset walk, where the query string would otherwise read it as absent.
+pub fn deserialize_after<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    String::deserialize(deserializer).map(Some)
+}
+
+/// The position after the last row of a keyset page.
+///
+/// It holds the sort key values of that row, tagged with the sort order they
+/// belong to so that a cursor cannot be replayed against a different order.
+/// Clients treat the encoded form as opaque.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Cursor {
+    #[serde(rename = "s")]
+    sort: String,
+    #[serde(rename = "k")]
+    keys: Vec<Value>,
+}
+
+impl Cursor {
+    pub fn new(sort: impl Into<String>, keys: Vec<Value>) -> Self {
+        Self {
+            sort: sort.into(),
+            keys,
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        let json = serde_json::to_vec(self).expect("cursor serializes to JSON");
+        URL_SAFE_NO_PAD.encode(json)
+    }
+
+    /// Decode an `after` parameter for a listing sorted by `sort`. An empty
+    /// parameter starts a keyset walk from the beginning.
+    pub fn decode(encoded: &str, sort: &str) -> Result<Option<Self>, ApiError> {
+        if encoded.is_empty() {
+            return Ok(None);
+        }
+        URL_SAFE_NO_PAD
+            .decode(encoded)
+            .ok()
+            .and_then(|json| serde_json::from_slice::<Cursor>(&json).ok())
+            .filter(|cursor| cursor.sort == sort)
+            .map(Some)
+            .ok_or_else(invalid_cursor)
+    }
+
+    /// The sort key at `index`, as the type of its column.
+    pub fn key<T: DeserializeOwned>(&self, index: usize) -> Result<T, ApiError> {
+        self.keys
+            .get(index)
+            .and_then(|key| T::deserialize(key).ok())
+            .ok_or_else(invalid_cursor)
+    }
+}
+
+fn invalid_cursor() -> ApiError {
+    let mut errors = ValidationErrors::default();
+    errors.add(
+        "after",
+        "invalid_cursor",
+        "is not a valid cursor for this listing",
+    );
+    ApiError::Validation(errors)
+}
+
+#[derive(Debug, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<i64>,
+    pub limit: i64,
+    pub next: Option<String>,
+    pub prev: Option<String>,
+}
+
+impl<T> Page<T> {
+    /// Build an offset page for `uri`, the links keep every other query
+    /// parameter.
+    pub fn offset(items: Vec<T>, total: i64, offset: i64, limit: i64, uri: &Uri) -> Self {
+        let window = |offset: i64| {
+            link(
+                uri,
+                &[("offset", offset.to_string()), ("limit", limit.to_string())],
+            )
+        };
+        let next = (offset.saturating_add(limit) < total).then(|| window(offset + limit));
+        let prev = (offset > 0).then(|| window((offset - limit).max(0)));
+
+        Self {
+            items,
+            total: Some(total),
+            offset: Some(offset),
+            limit,
+            next,
+            prev,
+        }
+    }
+
+    /// Build a keyset page for `uri`. `next` is the cursor after the last
+    /// item, if more rows follow.
+    pub fn keyset(items: Vec<T>, limit: i64, next: Option<Cursor>, uri: &Uri) -> Self {
+        let next = next.map(|cursor| {
+            link(
+                uri,
+                &[("after", cursor.encode()), ("limit", limit.to_string())],
+            )
+        });
+
+        Self {
+            items,
+            total: None,
+            offset: None,
+            limit,
+            next,
+            prev: None,
+        }
+    }
+}
+
+impl<T: Serialize> IntoResponse for Page<T> {
+    fn into_response(self) -> Response {
+        let links = link_header(self.next.as_deref(), self.prev.as_deref());
+        let mut response = Json(self).into_response();
+        if let Some(links) = links {
+            response.headers_mut().insert(header::LINK, links);
+        }
+        response
+    }
+}
+
+/// Rewrite the query string of `uri`, replacing the given parameters.
+pub fn link(uri: &Uri, params: &[(&str, String)]) -> String {
+    let mut query = form_urlencoded::Serializer::new(String::new());
+    for (name, value) in form_urlencoded::parse(uri.query().unwrap_or("").as_bytes()) {
+        if !params.iter().any(|(replaced, _)| *replaced == name) {
+            query.append_pair(&name, &value);
+        }
+    }
+    for (name, value) in params {
+        query.append_pair(name, value);
+    }
+    format!("{}?{}", uri.path(), query.finish())
+}
+
+/// Format `next`/`prev` as an RFC 8288 `Link` header value.
+pub fn link_header(next: Option<&str>, prev: Option<&str>) -> Option<HeaderValue> {
+    let links: Vec<String> = [(next, "next"), (prev, "prev")]
+        .into_iter()
+        .filter_map(|(target, rel)| target.map(|target| format!("<{target}>; rel=\"{rel}\"")))
+        .collect();
+    if links.is_empty() {
+        return None;
+    }
+    HeaderValue::from_str(&links.join(", ")).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn cursor_round_trips() {
+        let cursor = Cursor::new("-due_at,id", vec![json!("2026-01-01T00:00:00Z"), json!(7)]);
+        let decoded = Cursor::decode(&cursor.encode(), "-due_at,id")
+            .unwrap()
+            .unwrap();
+        assert_eq!(decoded.key::<String>(0).unwrap(), "2026-01-01T00:00:00Z");
+        assert_eq!(decoded.key::<i32>(1).unwrap(), 7);
+    }
+
+    #[test]
+    fn empty_cursor_starts_from_the_beginning() {
+        assert!(Cursor::decode("", "id").unwrap().is_none());
+    }
+
+    #[test]
+    fn cursor_for_another_order_is_rejected() {
+        let encoded = Cursor::new("id", vec![json!(7)]).encode();
+        assert!(matches!(
+            Cursor::decode(&encoded, "-id"),
+            Err(ApiError::Validation(_))
+        ));
+    }
+
+    #[test]
+    fn malformed_cursor_is_rejected() {
+        assert!(Cursor::decode("not a cursor", "id").is_err());
+        assert!(Cursor::decode(&URL_SAFE_NO_PAD.encode("[1]"), "id").is_err());
+        assert!(Cursor::decode(&URL_SAFE_NO_PAD.encode(r#"{"s":"id"}"#), "id").is_err());
+    }
+
+    #[test]
+    fn key_of_the_wrong_type_or_position_is_rejected() {
+        let cursor = Cursor::new("id", vec![json!("seven")]);
+        assert!(matches!(cursor.key::<i32>(0), Err(ApiError::Validation(_))));
+        assert!(cursor.key::<String>(1).is_err());
+        assert_eq!(
+            Cursor::new("due_at,id", vec![Value::Null])
+                .key::<Option<i32>>(0)
+                .unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,45 @@
+//! Support for JSON Merge Patch (RFC 7396) request bodies.
+
+use serde::{Deserialize, Deserializer};
+
+/// One member of a merge-patch document.
+///
+/// A member that is missing leaves the target untouched, an explicit `null`
+/// clears it and any other value replaces it. Fields using this type must be
+/// marked `#[serde(default)]` so that missing members deserialize as `Absent`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Patch<T> {
+    #[default]
+    Absent,
+    Null,
+    Value(T),
+}
+
+impl<T> Patch<T> {
+    pub fn is_absent(&self) -> bool {
+        matches!(self, Patch::Absent)
+    }
+
+    /// The new value, or `None` when the member was absent or null.
+    pub fn value(self) -> Option<T> {
+        match self {
+            Patch::Value(value) => Some(value),
+            Patch::Absent | Patch::Null => None,
+        }
+    }
+}
+
+impl<'de, T> Deserialize<'de> for Patch<T>
+where
+    T: Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(match Option::<T>::deserialize(deserializer)? {
+            Some(value) => Patch::Value(value),
+            None => Patch::Null,
+        })
+    }
+}
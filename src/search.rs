@@ -0,0 +1,297 @@
+//! Full-text search over todo descriptions.
+//!
+//! Descriptions are indexed into the `todos.search_vector` column by a
+//! trigger, using the text search configuration stored in `search_settings`.
+//! Queries use web search syntax (`"exact phrase"`, `or`, `-excluded`) and
+//! words ending in `*` match as prefixes.
+
+use axum::extract::{OriginalUri, State};
+use axum::routing::get;
+use axum::Router;
+use serde::Serialize;
+use sqlx::{PgPool, Postgres, QueryBuilder};
+
+use crate::auth::CurrentUser;
+use crate::config::SearchConfig;
+use crate::db;
+use crate::error::ApiError;
+use crate::pagination::{self, Page};
+use crate::todos::{self, ListOptions, Todo, TODO_COLUMNS};
+use crate::validation::{ValidQuery, ValidationErrors};
+use crate::AppState;
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/todos/search", get(search_todos))
+}
+
+/// Point the index at `language`, rebuilding every search vector if it
+/// changed since the last start.
+pub async fn sync_language(pool: &PgPool, language: &str) -> Result<(), sqlx::Error> {
+    let mut tx = db::begin_maintenance(pool).await?;
+    let changed = sqlx::query!(
+        r#"
+        UPDATE search_settings
+        SET language = $1::text::regconfig
+        WHERE language <> $1::text::regconfig
+        "#,
+        language
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected()
+        > 0;
+
+    if changed {
+        tracing::info!("Search language changed to {}, rebuilding index", language);
+        sqlx::query!(
+            "UPDATE todos SET search_vector = to_tsvector($1::text::regconfig, description)",
+            language
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+struct SearchHit {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    todo: Todo,
+    rank: f32,
+    /// Fragments of the description, HTML-escaped, with the matches between
+    /// the configured highlight markers.
+    snippet: String,
+}
+
+/// The description with the HTML special characters escaped, so that the
+/// snippet is safe to render as HTML. The highlight markers are added
+/// afterwards and stay as configured.
+const ESCAPED_DESCRIPTION: &str = "replace(replace(replace(replace(replace(description, \
+     '&', '&amp;'), '<', '&lt;'), '>', '&gt;'), '\"', '&quot;'), '''', '&#39;')";
+
+async fn search_todos(
+    State(state): State<AppState>,
+    user: CurrentUser,
+    OriginalUri(uri): OriginalUri,
+    ValidQuery(mut options): ValidQuery<ListOptions>,
+) -> Result<Page<SearchHit>, ApiError> {
+    // `q` is the search query here rather than a substring filter.
+    let Some(q) = options.q.take() else {
+        let mut errors = ValidationErrors::default();
+        errors.add("q", "required", "is required");
+        return Err(ApiError::Validation(errors));
+    };
+    let mut errors = ValidationErrors::default();
+    if options.after.is_some() {
+        errors.add(
+            "after",
+            "unsupported",
+            "search results are paginated by offset",
+        );
+    }
+    if options.sort.is_some() {
+        errors.add("sort", "unsupported", "search results are ordered by rank");
+    }
+    if !errors.is_empty() {
+        return Err(ApiError::Validation(errors));
+    }
+
+    let search = &state.config.search;
+    let limit = pagination::limit(options.limit, &state.config.pagination);
+    let offset = pagination::offset(options.offset);
+
+    let mut query = QueryBuilder::new(format!("SELECT {TODO_COLUMNS}, "));
+    query
+        .push("ts_rank_cd(search_vector, query) AS rank, ts_headline(")
+        .push_bind(&search.language)
+        .push(format!("::text::regconfig, {ESCAPED_DESCRIPTION}, query, "))
+        .push_bind(headline_options(search))
+        .push(") AS snippet");
+    push_from(&mut query, search, user, &q, &options);
+    query
+        .push(" ORDER BY rank DESC, id OFFSET ")
+        .push_bind(offset)
+        .push(" LIMIT ")
+        .push_bind(limit);
+    let mut tx = db::begin(&state.pool, user).await?;
+    let hits = query.build_query_as().fetch_all(&mut *tx).await?;
+
+    let mut query = QueryBuilder::new("SELECT COUNT(*)");
+    push_from(&mut query, search, user, &q, &options);
+    let total = query.build_query_scalar().fetch_one(&mut *tx).await?;
+    tx.commit().await?;
+
+    Ok(Page::offset(hits, total, offset, limit, &uri))
+}
+
+/// Append the `FROM` and `WHERE` clauses shared by the result and count
+/// queries. The parsed query is exposed to the select list as `query`.
+fn push_from(
+    query: &mut QueryBuilder<'_, Postgres>,
+    search: &SearchConfig,
+    user: CurrentUser,
+    q: &str,
+    options: &ListOptions,
+) {
+    query
+        .push(" FROM todos, (SELECT to_tsquery(")
+        .push_bind(search.language.clone())
+        .push("::text::regconfig, ")
+        .push_bind(to_tsquery(q))
+        .push(") AS query) AS search");
+
+    todos::push_filters(query, user, options);
+    query.push(" AND search_vector @@ query");
+}
+
+/// Translate `q` from web search syntax into a single `to_tsquery`
+/// expression, so that prefix terms combine with the others under the same
+/// operators: `foo or bar*` becomes `'foo' | 'bar':*`. Like
+/// `websearch_to_tsquery`, the implicit `and` binds tighter than `or`, so
+/// `a b or c` means `(a & b) | c`, and a stray `or` or unclosed quote is
+/// forgiven. Every word is quoted, so it
+/// cannot inject operators of its own.
+fn to_tsquery(q: &str) -> String {
+    let mut query = String::new();
+    let mut or = false;
+    let mut chars = q.chars().peekable();
+    while chars.peek().is_some() {
+        if chars.next_if(|c| c.is_whitespace()).is_some() {
+            continue;
+        }
+        let negated = chars.next_if_eq(&'-').is_some();
+        let term = if chars.next_if_eq(&'"').is_some() {
+            let phrase: String = chars.by_ref().take_while(|&c| c != '"').collect();
+            let words: Vec<String> = phrase.split_whitespace().map(quote).collect();
+            if words.is_empty() {
+                continue;
+            }
+            format!("({})", words.join(" <-> "))
+        } else {
+            let mut word = String::new();
+            while let Some(c) = chars.next_if(|&c| !c.is_whitespace() && c != '"') {
+                word.push(c);
+            }
+            if !negated && word.eq_ignore_ascii_case("or") {
+                or = !query.is_empty();
+                continue;
+            }
+            match word.strip_suffix('*') {
+                Some(stem) if !stem.is_empty() => format!("{}:*", quote(stem)),
+                _ if word.is_empty() => continue,
+                _ => quote(&word),
+            }
+        };
+        if !query.is_empty() {
+            query.push_str(if or { " | " } else { " & " });
+        }
+        if negated {
+            query.push('!');
+        }
+        query.push_str(&term);
+        or = false;
+    }
+    query
+}
+
+/// Quote a word as a `to_tsquery` lexeme, which the text search parser then
+/// normalizes like any other text.
+fn quote(word: &str) -> String {
+    format!("'{}'", word.replace('\\', "\\\\").replace('\'', "''"))
+}
+
+fn headline_options(search: &SearchConfig) -> String {
+    let quote = |value: &str| format!("\"{}\"", value.replace('"', "\"\""));
+    format!(
+        "StartSel={}, StopSel={}, MaxFragments=2, MaxWords=20, MinWords=5",
+        quote(&search.highlight_start),
+        quote(&search.highlight_stop)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn words_are_anded() {
+        assert_eq!(to_tsquery("  buy  milk "), "'buy' & 'milk'");
+        assert_eq!(to_tsquery(""), "");
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(to_tsquery("a b or c"), "'a' & 'b' | 'c'");
+        assert_eq!(to_tsquery("a or b c"), "'a' | 'b' & 'c'");
+    }
+
+    #[sqlx::test(migrator = "crate::migrate::MIGRATOR")]
+    async fn groups_like_websearch(pool: PgPool) {
+        for q in [
+            "cats dogs or fish",
+            "cats or dogs fish",
+            r#"-cats "big dogs" or fish"#,
+        ] {
+            let same: bool = sqlx::query_scalar(
+                "SELECT to_tsquery('english', $1) = websearch_to_tsquery('english', $2)",
+            )
+            .bind(to_tsquery(q))
+            .bind(q)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+            assert!(same, "{q}");
+        }
+    }
+
+    #[sqlx::test(migrator = "crate::migrate::MIGRATOR")]
+    async fn snippets_are_escaped(pool: PgPool) {
+        let snippet: String = sqlx::query_scalar(&format!(
+            "SELECT ts_headline('english', {ESCAPED_DESCRIPTION}, to_tsquery('english', 'cat'), \
+             'StartSel=<mark>, StopSel=</mark>') \
+             FROM (SELECT $1::text AS description) AS todos"
+        ))
+        .bind(r#"<img src=x onerror="alert('cat')"> cat & co"#)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(
+            snippet,
+            "&lt;img src=x onerror=&quot;alert(&#39;<mark>cat</mark>&#39;)&quot;&gt; \
+             <mark>cat</mark> &amp; co"
+        );
+    }
+
+    #[test]
+    fn prefixes_keep_their_operators() {
+        assert_eq!(to_tsquery("foo or bar*"), "'foo' | 'bar':*");
+        assert_eq!(to_tsquery("foo -bar*"), "'foo' & !'bar':*");
+        assert_eq!(to_tsquery("rep* -draft"), "'rep':* & !'draft'");
+    }
+
+    #[test]
+    fn phrases_and_negations() {
+        assert_eq!(
+            to_tsquery(r#"cats -"big dogs" OR fish"#),
+            "'cats' & !('big' <-> 'dogs') | 'fish'"
+        );
+        assert_eq!(
+            to_tsquery(r#"say "hello world"#),
+            "'say' & ('hello' <-> 'world')"
+        );
+    }
+
+    #[test]
+    fn stray_operators_are_ignored() {
+        assert_eq!(to_tsquery("or foo or"), "'foo'");
+        assert_eq!(to_tsquery(r#"- "" * foo"#), "'*' & 'foo'");
+    }
+
+    #[test]
+    fn words_cannot_inject_operators() {
+        assert_eq!(to_tsquery("a&b|!c"), "'a&b|!c'");
+        assert_eq!(to_tsquery(r"it's \'"), r"'it''s' & '\\'''");
+        assert_eq!(to_tsquery("x:*"), "'x:':*");
+    }
+}
@@ -0,0 +1,420 @@
+//! Tags that can be attached to todos.
+//!
+//! Every user has their own tags, whose names are unique regardless of case.
+//! Todos refer to their tags by name, and naming a tag that does not exist
+//! yet creates it.
+
+use axum::extract::{Path, State};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+
+use crate::auth::CurrentUser;
+use crate::db;
+use crate::error::ApiError;
+use crate::history;
+use crate::patch::Patch;
+use crate::validation::{self, TextRules, ValidJson, Validate, ValidationErrors};
+use crate::AppState;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/tags", get(get_tags).post(add_tag))
+        .route(
+            "/tags/:id",
+            get(get_tag).patch(patch_tag).delete(delete_tag),
+        )
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Tag {
+    id: i32,
+    name: String,
+    /// A `#rrggbb` color for display.
+    color: Option<String>,
+}
+
+pub const NAME: TextRules = TextRules {
+    min_len: 1,
+    max_len: 50,
+    allow_newlines: false,
+};
+
+/// The most tags a single todo can carry.
+pub const MAX_PER_TODO: usize = 20;
+
+fn validate_color(errors: &mut ValidationErrors, color: &mut String) {
+    *color = color.trim().to_ascii_lowercase();
+    let valid = color.len() == 7
+        && color.starts_with('#')
+        && color[1..].chars().all(|c| c.is_ascii_hexdigit());
+    if !valid {
+        errors.add("color", "invalid_format", "must be a #rrggbb color");
+    }
+}
+
+/// Validate the tag names given for a todo, trimming each one.
+pub fn validate_names(errors: &mut ValidationErrors, names: &mut [String]) {
+    if names.len() > MAX_PER_TODO {
+        errors.add(
+            "tags",
+            "too_many",
+            format!("must have at most {MAX_PER_TODO} tags"),
+        );
+    }
+    for (i, name) in names.iter_mut().enumerate() {
+        validation::text(errors, &format!("tags[{i}]"), name, &NAME);
+    }
+}
+
+/// Replace the tags of a todo with `names`, creating any that are missing.
+/// Bumps the version of the todo, and with it `updated_at`, and records the
+/// change in its history.
+///
+/// The tags belong to the owner of the todo, which in a shared list need not
+/// be the user making the change.
+pub async fn set_todo_tags(
+    conn: &mut PgConnection,
+    todo_id: i32,
+    names: &[String],
+) -> Result<(), sqlx::Error> {
+    let before = todo_tag_names(conn, todo_id).await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO tags (owner_id, name)
+        SELECT (SELECT owner_id FROM todos WHERE id = $1), name FROM unnest($2::text[]) AS name
+        ON CONFLICT (owner_id, (lower(name))) DO NOTHING
+        "#,
+        todo_id,
+        names
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query!("DELETE FROM todo_tags WHERE todo_id = $1", todo_id)
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO todo_tags (todo_id, tag_id)
+        SELECT $1, id FROM tags
+        WHERE owner_id = (SELECT owner_id FROM todos WHERE id = $1)
+            AND lower(name) = ANY(SELECT lower(unnest($2::text[])))
+        "#,
+        todo_id,
+        names
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    let after = todo_tag_names(conn, todo_id).await?;
+    if after == before {
+        return Ok(());
+    }
+    sqlx::query!(
+        "UPDATE todos SET version = version + 1 WHERE id = $1",
+        todo_id
+    )
+    .execute(&mut *conn)
+    .await?;
+    history::record_tags(conn, todo_id, &before, &after).await?;
+
+    Ok(())
+}
+
+/// The names of the tags of a todo, in the order they are shown in.
+async fn todo_tag_names(conn: &mut PgConnection, todo_id: i32) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT t.name FROM todo_tags tt JOIN tags t ON t.id = tt.tag_id
+        WHERE tt.todo_id = $1
+        ORDER BY lower(t.name)
+        "#,
+        todo_id
+    )
+    .fetch_all(conn)
+    .await
+}
+
+async fn get_tags(
+    State(pool): State<PgPool>,
+    user: CurrentUser,
+) -> Result<Json<Vec<Tag>>, ApiError> {
+    let mut tx = db::begin(&pool, user).await?;
+    let tags = sqlx::query_as!(
+        Tag,
+        "SELECT id, name, color FROM tags WHERE owner_id = $1 ORDER BY lower(name)",
+        user.id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(Json(tags))
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateTag {
+    name: String,
+    color: Option<String>,
+}
+
+impl Validate for CreateTag {
+    fn validate(&mut self, errors: &mut ValidationErrors) {
+        validation::text(errors, "name", &mut self.name, &NAME);
+        if let Some(color) = &mut self.color {
+            validate_color(errors, color);
+        }
+    }
+}
+
+async fn add_tag(
+    State(pool): State<PgPool>,
+    user: CurrentUser,
+    ValidJson(input): ValidJson<CreateTag>,
+) -> Result<Json<Tag>, ApiError> {
+    let mut tx = db::begin(&pool, user).await?;
+    let tag = sqlx::query_as!(
+        Tag,
+        r#"
+        INSERT INTO tags (owner_id, name, color)
+        VALUES ($1, $2, $3)
+        RETURNING id, name, color
+        "#,
+        user.id,
+        input.name,
+        input.color
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(Json(tag))
+}
+
+async fn get_tag(
+    State(pool): State<PgPool>,
+    user: CurrentUser,
+    Path(id): Path<i32>,
+) -> Result<Json<Tag>, ApiError> {
+    let mut tx = db::begin(&pool, user).await?;
+    let tag = sqlx::query_as!(
+        Tag,
+        "SELECT id, name, color FROM tags WHERE id = $1 AND owner_id = $2",
+        id,
+        user.id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(Json(tag))
+}
+
+/// A JSON Merge Patch document for a tag.
+#[derive(Debug, Deserialize)]
+struct PatchTag {
+    #[serde(default)]
+    name: Patch<String>,
+    #[serde(default)]
+    color: Patch<String>,
+}
+
+impl Validate for PatchTag {
+    fn validate(&mut self, errors: &mut ValidationErrors) {
+        validation::required(errors, "name", &mut self.name, |errors, value| {
+            validation::text(errors, "name", value, &NAME)
+        });
+        if let Patch::Value(color) = &mut self.color {
+            validate_color(errors, color);
+        }
+    }
+}
+
+async fn patch_tag(
+    State(pool): State<PgPool>,
+    user: CurrentUser,
+    Path(id): Path<i32>,
+    ValidJson(patch): ValidJson<PatchTag>,
+) -> Result<Json<Tag>, ApiError> {
+    user.require_unrestricted()?;
+    let name = patch.name.value();
+    let mut tx = db::begin(&pool, user).await?;
+    if let Some(name) = &name {
+        history::record_tag_change(&mut tx, id, Some(name)).await?;
+    }
+    let tag = sqlx::query_as!(
+        Tag,
+        r#"
+        UPDATE tags
+        SET name = COALESCE($1, name),
+            color = CASE WHEN $2 THEN $3 ELSE color END
+        WHERE id = $4 AND owner_id = $5
+        RETURNING id, name, color
+        "#,
+        name,
+        !patch.color.is_absent(),
+        patch.color.value(),
+        id,
+        user.id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    bump_tagged(&mut tx, tag.id).await?;
+    tx.commit().await?;
+
+    Ok(Json(tag))
+}
+
+/// Bump the version of every todo carrying the tag `id`, whose
+/// representation embeds it.
+async fn bump_tagged(conn: &mut PgConnection, id: i32) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE todos SET version = version + 1
+        WHERE id IN (SELECT todo_id FROM todo_tags WHERE tag_id = $1)
+        "#,
+        id
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Delete a tag, removing it from every todo that carries it and recording
+/// that in their history.
+async fn delete_tag(
+    State(pool): State<PgPool>,
+    user: CurrentUser,
+    Path(id): Path<i32>,
+) -> Result<Json<Tag>, ApiError> {
+    user.require_unrestricted()?;
+    let mut tx = db::begin(&pool, user).await?;
+    bump_tagged(&mut tx, id).await?;
+    history::record_tag_change(&mut tx, id, None).await?;
+    let tag = sqlx::query_as!(
+        Tag,
+        "DELETE FROM tags WHERE id = $1 AND owner_id = $2 RETURNING id, name, color",
+        id,
+        user.id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(Json(tag))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    /// A user with a todo tagged `home` and `urgent`, returning the user, the
+    /// todo and the id of `urgent`.
+    async fn seed(pool: &PgPool) -> (i32, i32, i32) {
+        let (user_id, todo_id): (i32, i32) = sqlx::query_as(
+            r#"
+            WITH owner AS (
+                INSERT INTO users (email, password_hash) VALUES ('ann@example.com', '')
+                RETURNING id
+            ), inbox AS (
+                INSERT INTO lists (name, inbox, owner_id) SELECT 'Inbox', true, id FROM owner
+                RETURNING id, owner_id
+            ), todo AS (
+                INSERT INTO todos (description, list_id, owner_id)
+                SELECT 'a', id, owner_id FROM inbox
+                RETURNING id, owner_id
+            )
+            SELECT owner_id, id FROM todo
+            "#,
+        )
+        .fetch_one(pool)
+        .await
+        .unwrap();
+        let mut conn = pool.acquire().await.unwrap();
+        set_todo_tags(&mut conn, todo_id, &["home".into(), "urgent".into()])
+            .await
+            .unwrap();
+        let tag_id = sqlx::query_scalar("SELECT id FROM tags WHERE name = 'urgent'")
+            .fetch_one(pool)
+            .await
+            .unwrap();
+        (user_id, todo_id, tag_id)
+    }
+
+    /// The tags before and after each change to them, oldest first.
+    async fn tag_changes(pool: &PgPool, todo_id: i32) -> Vec<(Value, Value)> {
+        sqlx::query_as(
+            r#"
+            SELECT before -> 'tags', after -> 'tags' FROM todo_history
+            WHERE todo_id = $1 AND after ? 'tags'
+            ORDER BY id
+            "#,
+        )
+        .bind(todo_id)
+        .fetch_all(pool)
+        .await
+        .unwrap()
+    }
+
+    async fn patch(
+        pool: &PgPool,
+        user_id: i32,
+        id: i32,
+        name: Patch<String>,
+        color: Patch<String>,
+    ) -> Tag {
+        let patch = PatchTag { name, color };
+        let Json(tag) = patch_tag(
+            State(pool.clone()),
+            CurrentUser::new(user_id),
+            Path(id),
+            ValidJson(patch),
+        )
+        .await
+        .unwrap();
+        tag
+    }
+
+    #[sqlx::test(migrator = "crate::migrate::MIGRATOR")]
+    async fn renaming_a_tag_is_recorded(pool: PgPool) {
+        let (user_id, todo_id, tag_id) = seed(&pool).await;
+        let asap = || Patch::Value("asap".to_string());
+        let tag = patch(&pool, user_id, tag_id, asap(), Patch::Absent).await;
+        assert_eq!(tag.name, "asap");
+        // Neither an unchanged name nor a color alone is a change of tags.
+        patch(&pool, user_id, tag_id, asap(), Patch::Absent).await;
+        patch(
+            &pool,
+            user_id,
+            tag_id,
+            Patch::Absent,
+            Patch::Value("#ff0000".into()),
+        )
+        .await;
+
+        let changes = tag_changes(&pool, todo_id).await;
+        assert_eq!(changes.len(), 2);
+        assert_eq!(
+            changes[1],
+            (json!(["home", "urgent"]), json!(["asap", "home"]))
+        );
+    }
+
+    #[sqlx::test(migrator = "crate::migrate::MIGRATOR")]
+    async fn deleting_a_tag_is_recorded(pool: PgPool) {
+        let (user_id, todo_id, tag_id) = seed(&pool).await;
+        let Json(tag) = delete_tag(State(pool.clone()), CurrentUser::new(user_id), Path(tag_id))
+            .await
+            .unwrap();
+        assert_eq!(tag.name, "urgent");
+
+        let changes = tag_changes(&pool, todo_id).await;
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[1], (json!(["home", "urgent"]), json!(["home"])));
+    }
+}
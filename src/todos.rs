@@ -0,0 +1,967 @@
+use axum::extract::{OriginalUri, Path, State};
+use axum::http::Uri;
+use axum::response::Response;
+use axum::routing::get;
+use axum::{Json, Router};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool, Postgres, QueryBuilder};
+
+use crate::auth::CurrentUser;
+use crate::db;
+use crate::error::ApiError;
+use crate::etag::{self, Conditional, IfMatch, Tagged, Validators};
+use crate::lists::{self, Role};
+use crate::pagination::{self, Cursor, Page};
+use crate::patch::Patch;
+use crate::tags::{self, Tag};
+use crate::validation::{self, TextRules, ValidJson, ValidQuery, Validate, ValidationErrors};
+use crate::AppState;
+
+use self::sort::Sort;
+use self::tree::Progress;
+
+mod batch;
+mod sort;
+mod trash;
+mod tree;
+
+pub use self::trash::purge_expired;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/todos", get(get_todos).post(add_todo))
+        .route(
+            "/todos/:id",
+            get(get_todo)
+                .put(update_todo)
+                .patch(patch_todo)
+                .delete(delete_todo),
+        )
+        .route("/todos/:id/children", get(get_children))
+        .route("/lists/:id/todos", get(get_list_todos).post(add_list_todo))
+        .merge(batch::router())
+        .merge(trash::router())
+}
+
+#[derive(Debug, Serialize, Clone, sqlx::FromRow)]
+pub struct Todo {
+    id: i32,
+    list_id: i32,
+    parent_id: Option<i32>,
+    description: String,
+    completed: bool,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    completed_at: Option<DateTime<Utc>>,
+    due_at: Option<DateTime<Utc>>,
+    /// The todo should not be started before this day.
+    start_on: Option<NaiveDate>,
+    priority: Priority,
+    tags: sqlx::types::Json<Vec<Tag>>,
+    progress: sqlx::types::Json<Progress>,
+    /// When the todo was moved to the trash.
+    deleted_at: Option<DateTime<Utc>>,
+    /// Bumped on every change, and sent as the `ETag`.
+    version: i32,
+}
+
+impl Todo {
+    fn tagged(self) -> Tagged<Todo> {
+        Tagged {
+            etag: etag::from_version(self.version),
+            body: self,
+        }
+    }
+}
+
+/// The select list matching [`Todo`], for queries built at runtime.
+pub const TODO_COLUMNS: &str =
+    "id, list_id, parent_id, description, completed, created_at, updated_at, completed_at, \
+     due_at, start_on, priority, \
+     COALESCE((SELECT json_agg(json_build_object('id', t.id, 'name', t.name, 'color', t.color) \
+     ORDER BY lower(t.name)) FROM todo_tags tt JOIN tags t ON t.id = tt.tag_id \
+     WHERE tt.todo_id = todos.id), '[]') AS tags, \
+     (WITH RECURSIVE subtasks AS ( \
+     SELECT s.id, s.completed FROM todos s WHERE s.parent_id = todos.id AND s.deleted_at IS NULL \
+     UNION \
+     SELECT s.id, s.completed FROM todos s JOIN subtasks ON s.parent_id = subtasks.id \
+     WHERE s.deleted_at IS NULL) \
+     SELECT json_build_object('completed', COUNT(*) FILTER (WHERE completed), 'total', COUNT(*)) \
+     FROM subtasks) AS progress, \
+     deleted_at, version";
+
+/// Lock the todo `id` for the rest of the transaction, so that it cannot
+/// change between checking `If-Match` and applying the change.
+async fn check_version(
+    conn: &mut PgConnection,
+    id: i32,
+    if_match: &IfMatch,
+) -> Result<(), ApiError> {
+    let version = sqlx::query_scalar!("SELECT version FROM todos WHERE id = $1 FOR UPDATE", id)
+        .fetch_one(conn)
+        .await?;
+    if_match.check(&etag::from_version(version))
+}
+
+/// Load a single todo of `user` with its tags and progress.
+pub async fn fetch_todo(
+    conn: &mut PgConnection,
+    user: CurrentUser,
+    id: i32,
+) -> Result<Todo, sqlx::Error> {
+    sqlx::query_as(&format!(
+        "SELECT {TODO_COLUMNS} FROM todos \
+         WHERE id = $1 AND deleted_at IS NULL AND list_role(list_id, $2) IS NOT NULL"
+    ))
+    .bind(id)
+    .bind(user.id)
+    .fetch_one(conn)
+    .await
+}
+
+/// How urgent a todo is. Stored as a small integer so that it sorts by
+/// urgency.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, sqlx::Type,
+)]
+#[serde(rename_all = "lowercase")]
+#[repr(i16)]
+pub enum Priority {
+    Low = 0,
+    #[default]
+    Normal = 1,
+    High = 2,
+    Urgent = 3,
+}
+
+// The query parameters for todos list. Passing `after` (empty for the
+// first page) switches from offset to keyset pagination; the filters and the
+// sort order apply to both.
+#[derive(Debug, Deserialize, Default)]
+pub struct ListOptions {
+    pub offset: Option<u64>,
+    pub limit: Option<u32>,
+    #[serde(default, deserialize_with = "pagination::deserialize_after")]
+    pub after: Option<String>,
+    /// Comma separated fields, each `field` or `-field`; see [`Sort`].
+    pub sort: Option<String>,
+    #[serde(skip)]
+    order: Sort,
+    pub list_id: Option<i32>,
+    /// Only the direct subtasks of this todo.
+    pub parent_id: Option<i32>,
+    pub completed: Option<bool>,
+    /// Case-insensitive substring of the description.
+    pub q: Option<String>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+    pub updated_after: Option<DateTime<Utc>>,
+    pub updated_before: Option<DateTime<Utc>>,
+    pub completed_after: Option<DateTime<Utc>>,
+    pub completed_before: Option<DateTime<Utc>>,
+    pub due_after: Option<DateTime<Utc>>,
+    pub due_before: Option<DateTime<Utc>>,
+    pub start_after: Option<NaiveDate>,
+    pub start_before: Option<NaiveDate>,
+    pub priority: Option<Priority>,
+    pub min_priority: Option<Priority>,
+    /// Only todos carrying these tags, see `tag_match`.
+    #[serde(default)]
+    pub tag: Vec<String>,
+    #[serde(default)]
+    pub tag_match: TagMatch,
+}
+
+/// Whether a todo must carry any or all of the requested tags.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TagMatch {
+    #[default]
+    Any,
+    All,
+}
+
+const SEARCH: TextRules = TextRules {
+    min_len: 1,
+    max_len: 200,
+    allow_newlines: false,
+};
+
+impl Validate for ListOptions {
+    fn validate(&mut self, errors: &mut ValidationErrors) {
+        if self.limit == Some(0) {
+            errors.add("limit", "out_of_range", "must be at least 1");
+        }
+        if self.offset.is_some() && self.after.is_some() {
+            errors.add(
+                "after",
+                "conflicting_parameters",
+                "cannot be combined with offset",
+            );
+        }
+        if let Some(sort) = &self.sort {
+            match Sort::parse(sort) {
+                Ok(order) => self.order = order,
+                Err((code, message)) => errors.add("sort", code, message),
+            }
+        }
+        if let Some(q) = &mut self.q {
+            validation::text(errors, "q", q, &SEARCH);
+        }
+        validation::ordered(
+            errors,
+            ("created_after", self.created_after),
+            ("created_before", self.created_before),
+        );
+        validation::ordered(
+            errors,
+            ("updated_after", self.updated_after),
+            ("updated_before", self.updated_before),
+        );
+        validation::ordered(
+            errors,
+            ("completed_after", self.completed_after),
+            ("completed_before", self.completed_before),
+        );
+        validation::ordered(
+            errors,
+            ("due_after", self.due_after),
+            ("due_before", self.due_before),
+        );
+        validation::ordered(
+            errors,
+            ("start_after", self.start_after),
+            ("start_before", self.start_before),
+        );
+        for (i, tag) in self.tag.iter_mut().enumerate() {
+            validation::text(errors, &format!("tag[{i}]"), tag, &tags::NAME);
+        }
+    }
+}
+
+/// Append the filters in `options` as a `WHERE` clause, restricted to the
+/// todos of `user`.
+pub fn push_filters(
+    query: &mut QueryBuilder<'_, Postgres>,
+    user: CurrentUser,
+    options: &ListOptions,
+) {
+    query
+        .push(" WHERE list_id IN (SELECT id FROM lists_with_role(")
+        .push_bind(user.id)
+        .push(", 'viewer')) AND deleted_at IS NULL");
+    if let Some(list_id) = options.list_id {
+        query.push(" AND list_id = ").push_bind(list_id);
+    }
+    if let Some(parent_id) = options.parent_id {
+        query.push(" AND parent_id = ").push_bind(parent_id);
+    }
+    if let Some(completed) = options.completed {
+        query.push(" AND completed = ").push_bind(completed);
+    }
+    if let Some(q) = &options.q {
+        query
+            .push(" AND description ILIKE ")
+            .push_bind(format!("%{}%", escape_like(q)));
+    }
+    let ranges = [
+        ("created_at", options.created_after, options.created_before),
+        ("updated_at", options.updated_after, options.updated_before),
+        (
+            "completed_at",
+            options.completed_after,
+            options.completed_before,
+        ),
+        ("due_at", options.due_after, options.due_before),
+    ];
+    for (column, after, before) in ranges {
+        if let Some(after) = after {
+            query.push(format!(" AND {column} > ")).push_bind(after);
+        }
+        if let Some(before) = before {
+            query.push(format!(" AND {column} < ")).push_bind(before);
+        }
+    }
+    if let Some(after) = options.start_after {
+        query.push(" AND start_on > ").push_bind(after);
+    }
+    if let Some(before) = options.start_before {
+        query.push(" AND start_on < ").push_bind(before);
+    }
+    if let Some(priority) = options.priority {
+        query.push(" AND priority = ").push_bind(priority);
+    }
+    if let Some(priority) = options.min_priority {
+        query.push(" AND priority >= ").push_bind(priority);
+    }
+    if !options.tag.is_empty() {
+        let mut names: Vec<String> = options.tag.iter().map(|tag| tag.to_lowercase()).collect();
+        names.sort();
+        names.dedup();
+        let tagged = "SELECT COUNT(*) FROM todo_tags tt JOIN tags t ON t.id = tt.tag_id \
+             WHERE tt.todo_id = todos.id AND lower(t.name) = ANY(";
+        match options.tag_match {
+            TagMatch::Any => {
+                query.push(format!(" AND ({tagged}"));
+                query.push_bind(names).push(")) > 0");
+            }
+            TagMatch::All => {
+                let count = names.len() as i64;
+                query.push(format!(" AND ({tagged}"));
+                query.push_bind(names).push(")) = ").push_bind(count);
+            }
+        }
+    }
+}
+
+/// Escape the `LIKE` wildcards in `value` so it matches literally.
+fn escape_like(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+async fn get_todos(
+    State(state): State<AppState>,
+    user: CurrentUser,
+    OriginalUri(uri): OriginalUri,
+    conditional: Conditional,
+    ValidQuery(options): ValidQuery<ListOptions>,
+) -> Result<Response, ApiError> {
+    let mut tx = db::begin(&state.pool, user).await?;
+    let page = list_todos(&mut tx, &state, user, &uri, &options).await?;
+    tx.commit().await?;
+
+    Ok(conditional.respond(page, &state.config.cache.todo_pages))
+}
+
+/// The todos of a single list, taking the same options as `/todos`.
+async fn get_list_todos(
+    State(state): State<AppState>,
+    user: CurrentUser,
+    Path(list_id): Path<i32>,
+    OriginalUri(uri): OriginalUri,
+    conditional: Conditional,
+    ValidQuery(mut options): ValidQuery<ListOptions>,
+) -> Result<Response, ApiError> {
+    let mut tx = db::begin(&state.pool, user).await?;
+    sqlx::query!(
+        "SELECT id FROM lists WHERE id = $1 AND list_role(id, $2) IS NOT NULL",
+        list_id,
+        user.id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    options.list_id = Some(list_id);
+    let page = list_todos(&mut tx, &state, user, &uri, &options).await?;
+    tx.commit().await?;
+
+    Ok(conditional.respond(page, &state.config.cache.todo_pages))
+}
+
+/// The direct subtasks of a todo, taking the same options as `/todos`.
+async fn get_children(
+    State(state): State<AppState>,
+    user: CurrentUser,
+    Path(id): Path<i32>,
+    OriginalUri(uri): OriginalUri,
+    conditional: Conditional,
+    ValidQuery(mut options): ValidQuery<ListOptions>,
+) -> Result<Response, ApiError> {
+    let mut tx = db::begin(&state.pool, user).await?;
+    sqlx::query!(
+        r#"
+        SELECT id FROM todos
+        WHERE id = $1 AND deleted_at IS NULL AND list_role(list_id, $2) IS NOT NULL
+        "#,
+        id,
+        user.id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    options.parent_id = Some(id);
+    let page = list_todos(&mut tx, &state, user, &uri, &options).await?;
+    tx.commit().await?;
+
+    Ok(conditional.respond(page, &state.config.cache.todo_pages))
+}
+
+async fn list_todos(
+    conn: &mut PgConnection,
+    state: &AppState,
+    user: CurrentUser,
+    uri: &Uri,
+    options: &ListOptions,
+) -> Result<Page<Todo>, ApiError> {
+    let limit = pagination::limit(options.limit, &state.config.pagination);
+    if let Some(after) = &options.after {
+        return get_todos_after(conn, user, uri, options, after, limit).await;
+    }
+    let offset = pagination::offset(options.offset);
+
+    let mut query = QueryBuilder::new(format!("SELECT {TODO_COLUMNS} FROM todos"));
+    push_filters(&mut query, user, options);
+    options.order.push_order_by(&mut query);
+    query
+        .push(" OFFSET ")
+        .push_bind(offset)
+        .push(" LIMIT ")
+        .push_bind(limit);
+    let todos = query.build_query_as().fetch_all(&mut *conn).await?;
+
+    let mut query = QueryBuilder::new("SELECT COUNT(*) FROM todos");
+    push_filters(&mut query, user, options);
+    let total = query.build_query_scalar().fetch_one(&mut *conn).await?;
+
+    Ok(Page::offset(todos, total, offset, limit, uri))
+}
+
+async fn get_todos_after(
+    conn: &mut PgConnection,
+    user: CurrentUser,
+    uri: &Uri,
+    options: &ListOptions,
+    after: &str,
+    limit: i64,
+) -> Result<Page<Todo>, ApiError> {
+    let order = &options.order;
+    let after = Cursor::decode(after, &order.to_param())?;
+
+    let mut query = QueryBuilder::new(format!("SELECT {TODO_COLUMNS} FROM todos"));
+    push_filters(&mut query, user, options);
+    if let Some(after) = &after {
+        order.push_after(&mut query, after)?;
+    }
+    order.push_order_by(&mut query);
+    // Fetch one extra row to learn whether another page follows.
+    query.push(" LIMIT ").push_bind(limit + 1);
+    let mut todos: Vec<Todo> = query.build_query_as().fetch_all(conn).await?;
+
+    let next = if todos.len() as i64 > limit {
+        todos.truncate(limit as usize);
+        todos.last().map(|todo| order.cursor(todo))
+    } else {
+        None
+    };
+
+    Ok(Page::keyset(todos, limit, next, uri))
+}
+
+const DESCRIPTION: TextRules = TextRules {
+    min_len: 1,
+    max_len: 1000,
+    allow_newlines: true,
+};
+
+#[derive(Debug, Deserialize)]
+struct CreateTodo {
+    /// Defaults to the list of the parent, or else the inbox.
+    list_id: Option<i32>,
+    parent_id: Option<i32>,
+    description: String,
+    due_at: Option<DateTime<Utc>>,
+    start_on: Option<NaiveDate>,
+    #[serde(default)]
+    priority: Priority,
+    /// Tag names, tags that do not exist yet are created.
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+impl Validate for CreateTodo {
+    fn validate(&mut self, errors: &mut ValidationErrors) {
+        validation::text(errors, "description", &mut self.description, &DESCRIPTION);
+        validate_schedule(errors, self.start_on, self.due_at);
+        tags::validate_names(errors, &mut self.tags);
+    }
+}
+
+/// A todo cannot start after the day, in UTC, that it is due.
+fn validate_schedule(
+    errors: &mut ValidationErrors,
+    start_on: Option<NaiveDate>,
+    due_at: Option<DateTime<Utc>>,
+) {
+    if let (Some(start_on), Some(due_at)) = (start_on, due_at) {
+        if start_on > due_at.date_naive() {
+            errors.add("start_on", "invalid_range", "must not be after due_at");
+        }
+    }
+}
+
+async fn add_todo(
+    State(pool): State<PgPool>,
+    user: CurrentUser,
+    ValidJson(input): ValidJson<CreateTodo>,
+) -> Result<Tagged<Todo>, ApiError> {
+    let mut tx = db::begin(&pool, user).await?;
+    let todo = create_todo(&mut tx, user, input).await?;
+    tx.commit().await?;
+
+    Ok(todo.tagged())
+}
+
+async fn add_list_todo(
+    State(pool): State<PgPool>,
+    user: CurrentUser,
+    Path(list_id): Path<i32>,
+    ValidJson(mut input): ValidJson<CreateTodo>,
+) -> Result<Tagged<Todo>, ApiError> {
+    let mut tx = db::begin(&pool, user).await?;
+    sqlx::query!(
+        "SELECT id FROM lists WHERE id = $1 AND list_role(id, $2) IS NOT NULL",
+        list_id,
+        user.id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    input.list_id = Some(list_id);
+    let todo = create_todo(&mut tx, user, input).await?;
+    tx.commit().await?;
+
+    Ok(todo.tagged())
+}
+
+async fn create_todo(
+    conn: &mut PgConnection,
+    user: CurrentUser,
+    input: CreateTodo,
+) -> Result<Todo, ApiError> {
+    let parent_id = input.parent_id.map_or(Patch::Absent, Patch::Value);
+    let placement = tree::place(conn, user, None, input.list_id, parent_id).await?;
+    let id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO todos (
+            owner_id, list_id, parent_id, description, completed, due_at, start_on, priority
+        )
+        VALUES ((SELECT owner_id FROM lists WHERE id = $1), $1, $2, $3, $4, $5, $6, $7)
+        RETURNING id
+        "#,
+        placement.list_id,
+        placement.parent_id,
+        input.description,
+        false,
+        input.due_at,
+        input.start_on,
+        input.priority as Priority
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    if !input.tags.is_empty() {
+        tags::set_todo_tags(conn, id, &input.tags).await?;
+    }
+    let todo = fetch_todo(conn, user, id).await?;
+
+    Ok(todo)
+}
+
+#[derive(Debug, Deserialize)]
+struct GetTodoQuery {
+    expand: Option<Expand>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Expand {
+    /// Nest every subtask, at any depth, under `children`.
+    Subtree,
+}
+
+impl Validate for GetTodoQuery {
+    fn validate(&mut self, _errors: &mut ValidationErrors) {}
+}
+
+async fn get_todo(
+    State(state): State<AppState>,
+    user: CurrentUser,
+    Path(id): Path<i32>,
+    conditional: Conditional,
+    ValidQuery(query): ValidQuery<GetTodoQuery>,
+) -> Result<Response, ApiError> {
+    let cache_control = &state.config.cache.todo;
+    let mut tx = db::begin(&state.pool, user).await?;
+    let response = match query.expand {
+        Some(Expand::Subtree) => {
+            let tree = tree::fetch_tree(&mut tx, user, id).await?;
+            let validators = Validators {
+                etag: etag::from_body(&tree),
+                last_modified: None,
+            };
+            if conditional.is_current(&validators) {
+                validators.not_modified(cache_control)
+            } else {
+                validators.apply(Json(tree), cache_control)
+            }
+        }
+        None => {
+            // Check the version first, so that a current copy is confirmed
+            // without loading the tags and progress.
+            let current = sqlx::query!(
+                r#"
+                SELECT version, updated_at FROM todos
+                WHERE id = $1 AND deleted_at IS NULL AND list_role(list_id, $2) IS NOT NULL
+                "#,
+                id,
+                user.id
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+            let validators = Validators {
+                etag: etag::from_version(current.version),
+                last_modified: Some(current.updated_at),
+            };
+            if conditional.is_current(&validators) {
+                validators.not_modified(cache_control)
+            } else {
+                let todo = fetch_todo(&mut tx, user, id).await?;
+                let validators = Validators {
+                    etag: etag::from_version(todo.version),
+                    last_modified: Some(todo.updated_at),
+                };
+                validators.apply(Json(todo), cache_control)
+            }
+        }
+    };
+    tx.commit().await?;
+
+    Ok(response)
+}
+
+/// Options shared by the handlers that change a todo.
+#[derive(Debug, Deserialize)]
+struct UpdateQuery {
+    /// When the todo gets completed, complete all its subtasks too.
+    #[serde(default)]
+    cascade: bool,
+}
+
+impl Validate for UpdateQuery {
+    fn validate(&mut self, _errors: &mut ValidationErrors) {}
+}
+
+/// A full replacement of a todo. Optional fields that are left out are
+/// cleared, the others are required. The todo stays in its list unless
+/// `list_id` is given.
+#[derive(Debug, Deserialize)]
+struct UpdateTodo {
+    list_id: Option<i32>,
+    parent_id: Option<i32>,
+    description: String,
+    completed: bool,
+    due_at: Option<DateTime<Utc>>,
+    start_on: Option<NaiveDate>,
+    priority: Priority,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+impl Validate for UpdateTodo {
+    fn validate(&mut self, errors: &mut ValidationErrors) {
+        validation::text(errors, "description", &mut self.description, &DESCRIPTION);
+        validate_schedule(errors, self.start_on, self.due_at);
+        tags::validate_names(errors, &mut self.tags);
+    }
+}
+
+async fn update_todo(
+    State(pool): State<PgPool>,
+    user: CurrentUser,
+    Path(id): Path<i32>,
+    if_match: IfMatch,
+    ValidQuery(query): ValidQuery<UpdateQuery>,
+    ValidJson(update_todo): ValidJson<UpdateTodo>,
+) -> Result<Tagged<Todo>, ApiError> {
+    let mut tx = db::begin(&pool, user).await?;
+    let parent_id = update_todo.parent_id.map_or(Patch::Null, Patch::Value);
+    let placement = tree::place(&mut tx, user, Some(id), update_todo.list_id, parent_id).await?;
+    check_version(&mut tx, id, &if_match).await?;
+    sqlx::query_scalar!(
+        r#"
+        UPDATE todos
+        SET description = $1, completed = $2, due_at = $3, start_on = $4, priority = $5,
+            list_id = $6, parent_id = $7
+        WHERE id = $8 AND deleted_at IS NULL AND list_role(list_id, $9) >= 'editor'
+        RETURNING id
+        "#,
+        update_todo.description,
+        update_todo.completed,
+        update_todo.due_at,
+        update_todo.start_on,
+        update_todo.priority as Priority,
+        placement.list_id,
+        placement.parent_id,
+        id,
+        user.id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    tags::set_todo_tags(&mut tx, id, &update_todo.tags).await?;
+    if query.cascade && update_todo.completed {
+        tree::complete_subtree(&mut tx, id).await?;
+    }
+    let todo = fetch_todo(&mut tx, user, id).await?;
+    tx.commit().await?;
+
+    Ok(todo.tagged())
+}
+
+/// A JSON Merge Patch document, only the members present are changed.
+#[derive(Debug, Default, Deserialize)]
+struct PatchTodo {
+    /// Moves the todo, with its subtasks, to another list.
+    #[serde(default)]
+    list_id: Patch<i32>,
+    /// Makes the todo a subtask, `null` makes it a top level todo again.
+    #[serde(default)]
+    parent_id: Patch<i32>,
+    #[serde(default)]
+    description: Patch<String>,
+    #[serde(default)]
+    completed: Patch<bool>,
+    #[serde(default)]
+    due_at: Patch<DateTime<Utc>>,
+    #[serde(default)]
+    start_on: Patch<NaiveDate>,
+    #[serde(default)]
+    priority: Patch<Priority>,
+    /// Replaces the whole set of tags, `null` removes them all.
+    #[serde(default)]
+    tags: Patch<Vec<String>>,
+}
+
+impl Validate for PatchTodo {
+    fn validate(&mut self, errors: &mut ValidationErrors) {
+        validation::required(
+            errors,
+            "description",
+            &mut self.description,
+            |errors, value| validation::text(errors, "description", value, &DESCRIPTION),
+        );
+        validation::required(errors, "completed", &mut self.completed, |_, _| {});
+        validation::required(errors, "priority", &mut self.priority, |_, _| {});
+        validation::required(errors, "list_id", &mut self.list_id, |_, _| {});
+        if let (Patch::Value(start_on), Patch::Value(due_at)) = (&self.start_on, &self.due_at) {
+            validate_schedule(errors, Some(*start_on), Some(*due_at));
+        }
+        if let Patch::Value(names) = &mut self.tags {
+            tags::validate_names(errors, names);
+        }
+    }
+}
+
+async fn patch_todo(
+    State(pool): State<PgPool>,
+    user: CurrentUser,
+    Path(id): Path<i32>,
+    if_match: IfMatch,
+    ValidQuery(query): ValidQuery<UpdateQuery>,
+    ValidJson(patch): ValidJson<PatchTodo>,
+) -> Result<Tagged<Todo>, ApiError> {
+    let mut tx = db::begin(&pool, user).await?;
+    let todo = apply_patch(&mut tx, user, id, &if_match, patch, query.cascade).await?;
+    tx.commit().await?;
+
+    Ok(todo.tagged())
+}
+
+async fn apply_patch(
+    conn: &mut PgConnection,
+    user: CurrentUser,
+    id: i32,
+    if_match: &IfMatch,
+    patch: PatchTodo,
+    cascade: bool,
+) -> Result<Todo, ApiError> {
+    let placement =
+        tree::place(conn, user, Some(id), patch.list_id.value(), patch.parent_id).await?;
+    check_version(conn, id, if_match).await?;
+    let completed = patch.completed.value();
+    sqlx::query_scalar!(
+        r#"
+        UPDATE todos
+        SET description = COALESCE($1, description),
+            completed = COALESCE($2, completed),
+            due_at = CASE WHEN $3 THEN $4 ELSE due_at END,
+            start_on = CASE WHEN $5 THEN $6 ELSE start_on END,
+            priority = COALESCE($7, priority),
+            list_id = $8,
+            parent_id = $9
+        WHERE id = $10 AND deleted_at IS NULL AND list_role(list_id, $11) >= 'editor'
+        RETURNING id
+        "#,
+        patch.description.value(),
+        completed,
+        !patch.due_at.is_absent(),
+        patch.due_at.value(),
+        !patch.start_on.is_absent(),
+        patch.start_on.value(),
+        patch.priority.value() as Option<Priority>,
+        placement.list_id,
+        placement.parent_id,
+        id,
+        user.id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    if !patch.tags.is_absent() {
+        let names = patch.tags.value().unwrap_or_default();
+        tags::set_todo_tags(conn, id, &names).await?;
+    }
+    if cascade && completed == Some(true) {
+        tree::complete_subtree(conn, id).await?;
+    }
+    let todo = fetch_todo(conn, user, id).await?;
+
+    Ok(todo)
+}
+
+#[derive(Debug, Deserialize)]
+struct DeleteQuery {
+    #[serde(default)]
+    children: Children,
+}
+
+/// What happens to the subtasks of a deleted todo.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Children {
+    /// Delete the whole subtree.
+    #[default]
+    Delete,
+    /// Move the direct subtasks up to the parent of the deleted todo.
+    Reparent,
+}
+
+impl Validate for DeleteQuery {
+    fn validate(&mut self, _errors: &mut ValidationErrors) {}
+}
+
+/// Move a todo to the trash, from where it can be restored.
+async fn delete_todo(
+    State(pool): State<PgPool>,
+    user: CurrentUser,
+    Path(id): Path<i32>,
+    if_match: IfMatch,
+    ValidQuery(query): ValidQuery<DeleteQuery>,
+) -> Result<Tagged<Todo>, ApiError> {
+    let mut tx = db::begin(&pool, user).await?;
+    let todo = trash_todo(&mut tx, user, id, &if_match, query.children).await?;
+    tx.commit().await?;
+
+    Ok(todo.tagged())
+}
+
+async fn trash_todo(
+    conn: &mut PgConnection,
+    user: CurrentUser,
+    id: i32,
+    if_match: &IfMatch,
+    children: Children,
+) -> Result<Todo, ApiError> {
+    let todo = fetch_todo(conn, user, id).await?;
+    lists::role(conn, user, todo.list_id)
+        .await?
+        .require(Role::Editor)?;
+    check_version(conn, id, if_match).await?;
+    if children == Children::Reparent {
+        tree::reparent_children(conn, id).await?;
+    }
+    trash::move_to_trash(conn, id).await?;
+    let todo = trash::fetch_deleted(conn, user, id).await?;
+
+    Ok(todo)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::body::to_bytes;
+    use axum::extract::FromRequestParts;
+    use axum::http::Request;
+    use serde_json::Value;
+
+    use super::*;
+    use crate::config::Config;
+
+    /// A user with an inbox holding `count` todos.
+    async fn seed(pool: &PgPool, count: i32) -> CurrentUser {
+        let user = sqlx::query_scalar!(
+            "INSERT INTO users (email, password_hash) VALUES ('ann@example.com', '') RETURNING id"
+        )
+        .fetch_one(pool)
+        .await
+        .unwrap();
+        sqlx::query!(
+            r#"
+            WITH inbox AS (
+                INSERT INTO lists (name, inbox, owner_id) VALUES ('Inbox', true, $1) RETURNING id
+            )
+            INSERT INTO todos (description, list_id, owner_id)
+            SELECT 'todo ' || n, inbox.id, $1 FROM inbox, generate_series(1, $2) AS n
+            "#,
+            user,
+            count
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+        CurrentUser::new(user)
+    }
+
+    async fn get(pool: PgPool, user: CurrentUser, uri: &str) -> Value {
+        let state = AppState {
+            pool,
+            config: Arc::new(Config::default()),
+        };
+        let (mut parts, ()) = Request::get(uri).body(()).unwrap().into_parts();
+        let conditional = Conditional::from_request_parts(&mut parts, &state)
+            .await
+            .unwrap();
+        let options = ValidQuery::from_request_parts(&mut parts, &state)
+            .await
+            .unwrap();
+        let response = get_todos(
+            State(state),
+            user,
+            OriginalUri(parts.uri),
+            conditional,
+            options,
+        )
+        .await
+        .unwrap();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[sqlx::test(migrator = "crate::migrate::MIGRATOR")]
+    async fn empty_after_starts_a_keyset_walk(pool: PgPool) {
+        let user = seed(&pool, 3).await;
+
+        let page = get(pool.clone(), user, "/todos?after=&limit=2").await;
+        assert_eq!(page["items"].as_array().unwrap().len(), 2);
+        assert!(page.get("offset").is_none());
+        assert!(page.get("total").is_none());
+        let next = page["next"].as_str().unwrap();
+        assert!(next.contains("after=") && !next.contains("after=&"));
+        assert!(!next.contains("offset"));
+
+        let page = get(pool, user, next).await;
+        assert_eq!(page["items"].as_array().unwrap().len(), 1);
+        assert_eq!(page["items"][0]["description"], "todo 3");
+        assert!(page["next"].is_null());
+    }
+
+    #[tokio::test]
+    async fn empty_after_conflicts_with_offset() {
+        let mut parts = Request::get("/todos?after=&offset=2")
+            .body(())
+            .unwrap()
+            .into_parts()
+            .0;
+        let rejected = ValidQuery::<ListOptions>::from_request_parts(&mut parts, &()).await;
+        assert!(matches!(rejected, Err(ApiError::Validation(_))));
+    }
+}
@@ -0,0 +1,228 @@
+//! Bulk changes to todos.
+//!
+//! `POST /todos/batch` runs a list of create, update, complete and delete
+//! operations in a single transaction and reports the outcome of each. In
+//! the default `atomic` mode the first failing operation rolls back the
+//! whole batch; in `best_effort` mode each operation runs in a savepoint and
+//! only its own changes are rolled back when it fails.
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use sqlx::{Connection, PgConnection, PgPool};
+
+use super::{apply_patch, create_todo, trash_todo, Children, CreateTodo, PatchTodo, Todo};
+use crate::auth::CurrentUser;
+use crate::db;
+use crate::error::{ApiError, Problem};
+use crate::etag::IfMatch;
+use crate::patch::Patch;
+use crate::validation::{ValidJson, Validate, ValidationErrors};
+use crate::AppState;
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/todos/batch", post(run_batch))
+}
+
+/// The most operations a single batch can hold.
+const MAX_OPERATIONS: usize = 1000;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Mode {
+    /// Apply every operation or none.
+    #[default]
+    Atomic,
+    /// Apply the operations that succeed and report the others.
+    BestEffort,
+}
+
+#[derive(Debug, Deserialize)]
+struct Batch {
+    #[serde(default)]
+    mode: Mode,
+    operations: Vec<Operation>,
+}
+
+/// A single change. Operations on existing todos may pass the `version` the
+/// change was based on, which then works like `If-Match`.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum Operation {
+    Create {
+        todo: CreateTodo,
+    },
+    /// Changes the todo like `PATCH /todos/:id`.
+    Update {
+        id: i32,
+        version: Option<i32>,
+        todo: PatchTodo,
+        #[serde(default)]
+        cascade: bool,
+    },
+    Complete {
+        id: i32,
+        version: Option<i32>,
+        #[serde(default)]
+        cascade: bool,
+    },
+    /// Moves the todo to the trash like `DELETE /todos/:id`.
+    Delete {
+        id: i32,
+        version: Option<i32>,
+        #[serde(default)]
+        children: Children,
+    },
+}
+
+impl Validate for Batch {
+    fn validate(&mut self, errors: &mut ValidationErrors) {
+        if self.operations.is_empty() {
+            errors.add("operations", "blank", "must contain at least one operation");
+        }
+        if self.operations.len() > MAX_OPERATIONS {
+            errors.add(
+                "operations",
+                "too_many",
+                format!("must have at most {MAX_OPERATIONS} operations"),
+            );
+        }
+        for (index, operation) in self.operations.iter_mut().enumerate() {
+            let mut nested = ValidationErrors::default();
+            match operation {
+                Operation::Create { todo } => todo.validate(&mut nested),
+                Operation::Update { todo, .. } => todo.validate(&mut nested),
+                Operation::Complete { .. } | Operation::Delete { .. } => {}
+            }
+            errors.nest(&format!("operations[{index}].todo"), nested);
+        }
+    }
+}
+
+/// The outcome of one operation, with the todo it left behind or the
+/// problem that stopped it.
+#[derive(Debug, Serialize)]
+struct Outcome {
+    status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    todo: Option<Todo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<Problem>,
+}
+
+impl Outcome {
+    fn failed(error: ApiError) -> Self {
+        Outcome {
+            status: error.status().as_u16(),
+            todo: None,
+            error: Some(error.problem()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct BatchResult {
+    /// Whether the changes of the successful operations were kept.
+    committed: bool,
+    results: Vec<Outcome>,
+}
+
+async fn run_operation(
+    conn: &mut PgConnection,
+    user: CurrentUser,
+    operation: Operation,
+) -> Result<Todo, ApiError> {
+    match operation {
+        Operation::Create { todo } => create_todo(conn, user, todo).await,
+        Operation::Update {
+            id,
+            version,
+            todo,
+            cascade,
+        } => apply_patch(conn, user, id, &IfMatch::version(version), todo, cascade).await,
+        Operation::Complete {
+            id,
+            version,
+            cascade,
+        } => {
+            let patch = PatchTodo {
+                completed: Patch::Value(true),
+                ..PatchTodo::default()
+            };
+            apply_patch(conn, user, id, &IfMatch::version(version), patch, cascade).await
+        }
+        Operation::Delete {
+            id,
+            version,
+            children,
+        } => trash_todo(conn, user, id, &IfMatch::version(version), children).await,
+    }
+}
+
+/// Run the operations in order, later ones seeing the changes of earlier
+/// ones. A failed atomic batch answers with the status of the operation
+/// that failed, and every other operation is reported as 424 Failed
+/// Dependency. Server errors abort the batch in either mode.
+async fn run_batch(
+    State(pool): State<PgPool>,
+    user: CurrentUser,
+    ValidJson(batch): ValidJson<Batch>,
+) -> Result<Response, ApiError> {
+    let count = batch.operations.len();
+    let mut tx = db::begin(&pool, user).await?;
+    let mut results = Vec::with_capacity(count);
+    let mut failed = None;
+    for (index, operation) in batch.operations.into_iter().enumerate() {
+        let mut savepoint = tx.begin().await?;
+        match run_operation(&mut savepoint, user, operation).await {
+            Ok(todo) => {
+                savepoint.commit().await?;
+                results.push(Outcome {
+                    status: StatusCode::OK.as_u16(),
+                    todo: Some(todo),
+                    error: None,
+                });
+            }
+            Err(error @ (ApiError::Internal(_) | ApiError::Unavailable(_))) => return Err(error),
+            Err(error) => {
+                savepoint.rollback().await?;
+                tracing::debug!("Batch operation {index} failed: {error}");
+                let status = error.status();
+                results.push(Outcome::failed(error));
+                if batch.mode == Mode::Atomic {
+                    failed = Some((index, status));
+                    break;
+                }
+            }
+        }
+    }
+
+    let Some((index, status)) = failed else {
+        tx.commit().await?;
+        let result = BatchResult {
+            committed: true,
+            results,
+        };
+        return Ok(Json(result).into_response());
+    };
+    tx.rollback().await?;
+    let failure = results.pop().expect("the failed operation has an outcome");
+    let mut results: Vec<Outcome> = (0..count)
+        .map(|_| {
+            Outcome::failed(ApiError::Rejected(
+                StatusCode::FAILED_DEPENDENCY,
+                format!("Not applied because operation {index} failed."),
+            ))
+        })
+        .collect();
+    results[index] = failure;
+    let result = BatchResult {
+        committed: false,
+        results,
+    };
+
+    Ok((status, Json(result)).into_response())
+}
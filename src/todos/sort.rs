@@ -0,0 +1,349 @@
+//! Sort orders for the todo list.
+//!
+//! A sort parameter is a comma separated list of whitelisted fields, each
+//! optionally prefixed with `-` for descending order, e.g.
+//! `-completed,created_at`. The id is appended as a final tie breaker so that
+//! every order is total, which keyset pagination relies on.
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde_json::{json, Value};
+use sqlx::{Postgres, QueryBuilder};
+
+use super::{Priority, Todo};
+use crate::error::ApiError;
+use crate::pagination::Cursor;
+
+/// A column the todo list can be ordered by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SortField {
+    Id,
+    Description,
+    Completed,
+    CreatedAt,
+    UpdatedAt,
+    CompletedAt,
+    DueAt,
+    StartOn,
+    Priority,
+}
+
+const FIELDS: &[SortField] = &[
+    SortField::Id,
+    SortField::Description,
+    SortField::Completed,
+    SortField::CreatedAt,
+    SortField::UpdatedAt,
+    SortField::CompletedAt,
+    SortField::DueAt,
+    SortField::StartOn,
+    SortField::Priority,
+];
+
+impl SortField {
+    fn parse(name: &str) -> Option<Self> {
+        FIELDS.iter().copied().find(|field| field.name() == name)
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            SortField::Id => "id",
+            SortField::Description => "description",
+            SortField::Completed => "completed",
+            SortField::CreatedAt => "created_at",
+            SortField::UpdatedAt => "updated_at",
+            SortField::CompletedAt => "completed_at",
+            SortField::DueAt => "due_at",
+            SortField::StartOn => "start_on",
+            SortField::Priority => "priority",
+        }
+    }
+
+    /// The expression ordered on. Nulls order as if they were the largest
+    /// value, like Postgres does by default, but the expression itself is
+    /// never null so that keyset comparisons work.
+    fn expr(self) -> &'static str {
+        match self {
+            SortField::CompletedAt => "COALESCE(completed_at, 'infinity')",
+            SortField::DueAt => "COALESCE(due_at, 'infinity')",
+            SortField::StartOn => "COALESCE(start_on, 'infinity')",
+            field => field.name(),
+        }
+    }
+
+    /// The value of this field in `todo`, as stored in a cursor.
+    fn key(self, todo: &Todo) -> Value {
+        match self {
+            SortField::Id => json!(todo.id),
+            SortField::Description => json!(todo.description),
+            SortField::Completed => json!(todo.completed),
+            SortField::CreatedAt => json!(todo.created_at),
+            SortField::UpdatedAt => json!(todo.updated_at),
+            SortField::CompletedAt => json!(todo.completed_at),
+            SortField::DueAt => json!(todo.due_at),
+            SortField::StartOn => json!(todo.start_on),
+            SortField::Priority => json!(todo.priority),
+        }
+    }
+
+    /// Bind the cursor key at `index` so it compares against [`Self::expr`].
+    fn push_key(
+        self,
+        query: &mut QueryBuilder<'_, Postgres>,
+        cursor: &Cursor,
+        index: usize,
+    ) -> Result<(), ApiError> {
+        match self {
+            SortField::Id => {
+                query.push_bind(cursor.key::<i32>(index)?);
+            }
+            SortField::Description => {
+                query.push_bind(cursor.key::<String>(index)?);
+            }
+            SortField::Completed => {
+                query.push_bind(cursor.key::<bool>(index)?);
+            }
+            SortField::CreatedAt | SortField::UpdatedAt => {
+                query.push_bind(cursor.key::<DateTime<Utc>>(index)?);
+            }
+            SortField::CompletedAt | SortField::DueAt => {
+                query
+                    .push("COALESCE(")
+                    .push_bind(cursor.key::<Option<DateTime<Utc>>>(index)?)
+                    .push("::timestamptz, 'infinity')");
+            }
+            SortField::StartOn => {
+                query
+                    .push("COALESCE(")
+                    .push_bind(cursor.key::<Option<NaiveDate>>(index)?)
+                    .push("::date, 'infinity')");
+            }
+            SortField::Priority => {
+                query.push_bind(cursor.key::<Priority>(index)?);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SortKey {
+    field: SortField,
+    descending: bool,
+}
+
+impl SortKey {
+    fn direction(self) -> &'static str {
+        if self.descending {
+            "DESC"
+        } else {
+            "ASC"
+        }
+    }
+}
+
+/// A validated sort order, always ending in the id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sort(Vec<SortKey>);
+
+impl Default for Sort {
+    fn default() -> Self {
+        Sort(vec![SortKey {
+            field: SortField::Id,
+            descending: false,
+        }])
+    }
+}
+
+impl Sort {
+    /// Parse a `sort` parameter, returning a message for the client if it
+    /// names an unknown or repeated field.
+    pub fn parse(param: &str) -> Result<Self, (&'static str, String)> {
+        let mut keys: Vec<SortKey> = Vec::new();
+        for part in param.split(',').map(str::trim) {
+            let (descending, name) = match part.strip_prefix('-') {
+                Some(name) => (true, name),
+                None => (false, part),
+            };
+            let Some(field) = SortField::parse(name) else {
+                let known: Vec<_> = FIELDS.iter().map(|field| field.name()).collect();
+                return Err((
+                    "unknown_field",
+                    format!(
+                        "cannot sort by {name:?}, expected one of {}",
+                        known.join(", ")
+                    ),
+                ));
+            };
+            if keys.iter().any(|key| key.field == field) {
+                return Err(("duplicate_field", format!("{name:?} is listed twice")));
+            }
+            keys.push(SortKey { field, descending });
+        }
+
+        if !keys.iter().any(|key| key.field == SortField::Id) {
+            keys.push(SortKey {
+                field: SortField::Id,
+                descending: false,
+            });
+        }
+        Ok(Sort(keys))
+    }
+
+    /// The canonical spelling, also used to tag cursors.
+    pub fn to_param(&self) -> String {
+        let keys: Vec<String> = self
+            .0
+            .iter()
+            .map(|key| {
+                let sign = if key.descending { "-" } else { "" };
+                format!("{sign}{}", key.field.name())
+            })
+            .collect();
+        keys.join(",")
+    }
+
+    pub fn push_order_by(&self, query: &mut QueryBuilder<'_, Postgres>) {
+        let keys: Vec<String> = self
+            .0
+            .iter()
+            .map(|key| format!("{} {}", key.field.expr(), key.direction()))
+            .collect();
+        query.push(" ORDER BY ").push(keys.join(", "));
+    }
+
+    /// Restrict `query` to the rows after `cursor` in this order:
+    /// `(k1 > v1) OR (k1 = v1 AND k2 > v2) OR ...`, with `<` for descending
+    /// keys.
+    pub fn push_after(
+        &self,
+        query: &mut QueryBuilder<'_, Postgres>,
+        cursor: &Cursor,
+    ) -> Result<(), ApiError> {
+        query.push(" AND (");
+        for (i, key) in self.0.iter().enumerate() {
+            if i > 0 {
+                query.push(" OR ");
+            }
+            query.push("(");
+            for (j, equal) in self.0[..i].iter().enumerate() {
+                query.push(format!("{} = ", equal.field.expr()));
+                equal.field.push_key(query, cursor, j)?;
+                query.push(" AND ");
+            }
+            let op = if key.descending { "<" } else { ">" };
+            query.push(format!("{} {op} ", key.field.expr()));
+            key.field.push_key(query, cursor, i)?;
+            query.push(")");
+        }
+        query.push(")");
+        Ok(())
+    }
+
+    pub fn cursor(&self, todo: &Todo) -> Cursor {
+        let keys = self.0.iter().map(|key| key.field.key(todo)).collect();
+        Cursor::new(self.to_param(), keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn after(sort: &str, keys: Vec<Value>) -> Result<String, ApiError> {
+        let sort = Sort::parse(sort).unwrap();
+        let cursor = Cursor::new(sort.to_param(), keys);
+        let mut query = QueryBuilder::new("WHERE true");
+        sort.push_after(&mut query, &cursor)?;
+        Ok(query.sql().to_string())
+    }
+
+    #[test]
+    fn parse_appends_the_id_as_tie_breaker() {
+        assert_eq!(
+            Sort::parse("-completed, created_at").unwrap().to_param(),
+            "-completed,created_at,id"
+        );
+        assert_eq!(
+            Sort::parse("-id,priority").unwrap().to_param(),
+            "-id,priority"
+        );
+        assert_eq!(Sort::parse("id").unwrap(), Sort::default());
+    }
+
+    #[test]
+    fn parse_rejects_unknown_and_repeated_fields() {
+        assert_eq!(Sort::parse("owner_id").unwrap_err().0, "unknown_field");
+        assert_eq!(Sort::parse("").unwrap_err().0, "unknown_field");
+        assert_eq!(Sort::parse("--id").unwrap_err().0, "unknown_field");
+        assert_eq!(
+            Sort::parse("due_at,-due_at").unwrap_err().0,
+            "duplicate_field"
+        );
+    }
+
+    #[test]
+    fn to_param_round_trips() {
+        let sort = Sort::parse("-start_on,description").unwrap();
+        assert_eq!(Sort::parse(&sort.to_param()).unwrap(), sort);
+    }
+
+    #[test]
+    fn order_by_puts_missing_dates_last() {
+        let mut query = QueryBuilder::new("SELECT 1");
+        Sort::parse("-due_at,start_on")
+            .unwrap()
+            .push_order_by(&mut query);
+        assert_eq!(
+            query.sql(),
+            "SELECT 1 ORDER BY COALESCE(due_at, 'infinity') DESC, \
+             COALESCE(start_on, 'infinity') ASC, id ASC"
+        );
+    }
+
+    #[test]
+    fn after_chains_the_keys_with_or() {
+        assert_eq!(
+            after("id", vec![json!(7)]).unwrap(),
+            "WHERE true AND ((id > $1))"
+        );
+        assert_eq!(
+            after(
+                "-completed,priority",
+                vec![json!(true), json!("high"), json!(7)]
+            )
+            .unwrap(),
+            "WHERE true AND ((completed < $1) \
+             OR (completed = $2 AND priority > $3) \
+             OR (completed = $4 AND priority = $5 AND id > $6))"
+        );
+    }
+
+    #[test]
+    fn after_compares_missing_dates_as_infinity() {
+        assert_eq!(
+            after("-due_at", vec![Value::Null, json!(7)]).unwrap(),
+            "WHERE true AND ((COALESCE(due_at, 'infinity') < \
+             COALESCE($1::timestamptz, 'infinity')) \
+             OR (COALESCE(due_at, 'infinity') = COALESCE($2::timestamptz, 'infinity') \
+             AND id > $3))"
+        );
+        assert_eq!(
+            after("start_on", vec![json!("2026-01-31"), json!(7)]).unwrap(),
+            "WHERE true AND ((COALESCE(start_on, 'infinity') > \
+             COALESCE($1::date, 'infinity')) \
+             OR (COALESCE(start_on, 'infinity') = COALESCE($2::date, 'infinity') \
+             AND id > $3))"
+        );
+    }
+
+    #[test]
+    fn after_rejects_keys_that_do_not_fit_the_order() {
+        assert!(matches!(
+            after("due_at", vec![json!("tomorrow"), json!(7)]),
+            Err(ApiError::Validation(_))
+        ));
+        assert!(after("completed", vec![json!(true)]).is_err());
+    }
+}
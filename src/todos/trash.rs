@@ -0,0 +1,275 @@
+//! The trash.
+//!
+//! Deleting a todo only sets its `deleted_at`, which hides it, along with
+//! the subtasks deleted with it, from everything but the trash. Todos deleted
+//! together share a `deletion_id`, by which they are restored together. Todos can be
+//! restored from the trash until they are purged, either by emptying it or
+//! once they have been there longer than `trash.retention_days`.
+
+use axum::extract::{OriginalUri, Path, State};
+use axum::http::StatusCode;
+use axum::routing::{delete, post};
+use axum::Router;
+use serde::Deserialize;
+use sqlx::{PgConnection, PgPool};
+
+use super::tree::SUBTREE;
+use super::{fetch_todo, Todo, TODO_COLUMNS};
+use crate::auth::CurrentUser;
+use crate::config::TrashConfig;
+use crate::db;
+use crate::error::ApiError;
+use crate::etag::Tagged;
+use crate::lists::{self, Role};
+use crate::pagination::{self, Page};
+use crate::validation::{ValidQuery, Validate, ValidationErrors};
+use crate::AppState;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/trash", delete(empty_trash).get(get_trash))
+        .route("/todos/:id/restore", post(restore_todo))
+}
+
+/// Move the todo `id` and its subtasks to the trash. They share one
+/// `deletion_id`, which is how [`restore_todo`] finds them again.
+pub async fn move_to_trash(conn: &mut PgConnection, id: i32) -> Result<(), sqlx::Error> {
+    sqlx::query(&format!(
+        "{SUBTREE}, deletion AS (SELECT gen_random_uuid() AS id) \
+         UPDATE todos SET deleted_at = now(), deletion_id = (SELECT id FROM deletion) \
+         WHERE id IN (SELECT id FROM subtree)"
+    ))
+    .bind(id)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Load a todo in the trash.
+pub async fn fetch_deleted(
+    conn: &mut PgConnection,
+    user: CurrentUser,
+    id: i32,
+) -> Result<Todo, sqlx::Error> {
+    sqlx::query_as(&format!(
+        "SELECT {TODO_COLUMNS} FROM todos \
+         WHERE id = $1 AND deleted_at IS NOT NULL \
+         AND list_id IN (SELECT id FROM lists_with_role($2, 'viewer'))"
+    ))
+    .bind(id)
+    .bind(user.id)
+    .fetch_one(conn)
+    .await
+}
+
+/// Purge every todo that has been in the trash longer than the retention,
+/// returning how many were removed.
+pub async fn purge_expired(pool: &PgPool, config: &TrashConfig) -> Result<u64, sqlx::Error> {
+    let mut tx = db::begin_maintenance(pool).await?;
+    let purged = sqlx::query!(
+        "DELETE FROM todos WHERE deleted_at < now() - make_interval(days => $1)",
+        config.retention_days as i32
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+    tx.commit().await?;
+    Ok(purged)
+}
+
+#[derive(Debug, Deserialize)]
+struct TrashQuery {
+    limit: Option<u32>,
+    offset: Option<u64>,
+}
+
+impl Validate for TrashQuery {
+    fn validate(&mut self, errors: &mut ValidationErrors) {
+        if self.limit == Some(0) {
+            errors.add("limit", "out_of_range", "must be at least 1");
+        }
+    }
+}
+
+/// The todos in the trash of every list the caller can see, most recently
+/// deleted first.
+async fn get_trash(
+    State(state): State<AppState>,
+    user: CurrentUser,
+    OriginalUri(uri): OriginalUri,
+    ValidQuery(query): ValidQuery<TrashQuery>,
+) -> Result<Page<Todo>, ApiError> {
+    let limit = pagination::limit(query.limit, &state.config.pagination);
+    let offset = pagination::offset(query.offset);
+
+    let mut tx = db::begin(&state.pool, user).await?;
+    let todos = sqlx::query_as(&format!(
+        "SELECT {TODO_COLUMNS} FROM todos \
+         WHERE deleted_at IS NOT NULL \
+         AND list_id IN (SELECT id FROM lists_with_role($1, 'viewer')) \
+         ORDER BY deleted_at DESC, id OFFSET $2 LIMIT $3"
+    ))
+    .bind(user.id)
+    .bind(offset)
+    .bind(limit)
+    .fetch_all(&mut *tx)
+    .await?;
+    let total = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!" FROM todos
+        WHERE deleted_at IS NOT NULL
+            AND list_id IN (SELECT id FROM lists_with_role($1, 'viewer'))
+        "#,
+        user.id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(Page::offset(todos, total, offset, limit, &uri))
+}
+
+/// Take a todo out of the trash together with the subtasks deleted with it.
+/// If its parent is still in the trash, it becomes a top level todo. Todos
+/// cannot be restored into an archived list.
+async fn restore_todo(
+    State(pool): State<PgPool>,
+    user: CurrentUser,
+    Path(id): Path<i32>,
+) -> Result<Tagged<Todo>, ApiError> {
+    let mut tx = db::begin(&pool, user).await?;
+    let todo = fetch_deleted(&mut tx, user, id).await?;
+    lists::role(&mut tx, user, todo.list_id)
+        .await?
+        .require(Role::Editor)?;
+    let archived = sqlx::query_scalar!("SELECT archived FROM lists WHERE id = $1", todo.list_id)
+        .fetch_one(&mut *tx)
+        .await?;
+    if archived {
+        return Err(ApiError::Unprocessable("The list is archived.".into()));
+    }
+    sqlx::query!(
+        r#"
+        WITH RECURSIVE restored AS (
+            SELECT id, deletion_id FROM todos WHERE id = $1
+            UNION
+            SELECT t.id, t.deletion_id FROM todos t
+            JOIN restored ON t.parent_id = restored.id AND t.deletion_id = restored.deletion_id
+        )
+        UPDATE todos SET deleted_at = NULL, deletion_id = NULL
+        WHERE id IN (SELECT id FROM restored)
+        "#,
+        id
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        r#"
+        UPDATE todos SET parent_id = NULL
+        WHERE id = $1
+            AND parent_id IN (SELECT id FROM todos WHERE deleted_at IS NOT NULL)
+        "#,
+        id
+    )
+    .execute(&mut *tx)
+    .await?;
+    let todo = fetch_todo(&mut tx, user, id).await?;
+    tx.commit().await?;
+
+    Ok(todo.tagged())
+}
+
+/// Purge the trash of every list the caller administers.
+async fn empty_trash(
+    State(pool): State<PgPool>,
+    user: CurrentUser,
+) -> Result<StatusCode, ApiError> {
+    let mut tx = db::begin(&pool, user).await?;
+    sqlx::query!(
+        r#"
+        DELETE FROM todos
+        WHERE deleted_at IS NOT NULL
+            AND list_id IN (SELECT id FROM lists_with_role($1, 'admin'))
+        "#,
+        user.id
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A user with a list holding a todo `a` and its subtask `b`, returning
+    /// the user and both ids.
+    async fn seed(pool: &PgPool) -> (i32, i32, i32) {
+        let (user_id, a, b): (i32, i32, i32) = sqlx::query_as(
+            r#"
+            WITH owner AS (
+                INSERT INTO users (email, password_hash) VALUES ('ann@example.com', '')
+                RETURNING id
+            ), list AS (
+                INSERT INTO lists (name, owner_id) SELECT 'Work', id FROM owner
+                RETURNING id, owner_id
+            ), a AS (
+                INSERT INTO todos (description, list_id, owner_id)
+                SELECT 'a', id, owner_id FROM list
+                RETURNING id, list_id, owner_id
+            ), b AS (
+                INSERT INTO todos (description, list_id, owner_id, parent_id)
+                SELECT 'b', list_id, owner_id, id FROM a
+                RETURNING id
+            )
+            SELECT owner.id, a.id, b.id FROM owner, a, b
+            "#,
+        )
+        .fetch_one(pool)
+        .await
+        .unwrap();
+        (user_id, a, b)
+    }
+
+    async fn is_deleted(pool: &PgPool, id: i32) -> bool {
+        sqlx::query_scalar("SELECT deleted_at IS NOT NULL FROM todos WHERE id = $1")
+            .bind(id)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    #[sqlx::test(migrator = "crate::migrate::MIGRATOR")]
+    async fn restores_only_what_was_deleted_together(pool: PgPool) {
+        let (user_id, a, b) = seed(&pool).await;
+        // Both deletions get the same now() in one transaction.
+        let mut tx = pool.begin().await.unwrap();
+        move_to_trash(&mut tx, b).await.unwrap();
+        move_to_trash(&mut tx, a).await.unwrap();
+        tx.commit().await.unwrap();
+
+        restore_todo(State(pool.clone()), CurrentUser::new(user_id), Path(a))
+            .await
+            .unwrap();
+        assert!(!is_deleted(&pool, a).await);
+        assert!(is_deleted(&pool, b).await);
+    }
+
+    #[sqlx::test(migrator = "crate::migrate::MIGRATOR")]
+    async fn cannot_restore_into_an_archived_list(pool: PgPool) {
+        let (user_id, a, _) = seed(&pool).await;
+        move_to_trash(&mut pool.acquire().await.unwrap(), a)
+            .await
+            .unwrap();
+        sqlx::query("UPDATE lists SET archived = true")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let result = restore_todo(State(pool.clone()), CurrentUser::new(user_id), Path(a)).await;
+        assert!(matches!(result, Err(ApiError::Unprocessable(_))));
+        assert!(is_deleted(&pool, a).await);
+    }
+}
@@ -0,0 +1,372 @@
+//! Subtasks.
+//!
+//! A todo may have a parent in the same list, forming a tree. Moving a todo
+//! to another list takes its subtree along, while moving a subtask on its
+//! own detaches it from its parent.
+
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+use sqlx::PgConnection;
+
+use super::{Todo, TODO_COLUMNS};
+use crate::auth::CurrentUser;
+use crate::error::ApiError;
+use crate::lists::{self, Role};
+use crate::patch::Patch;
+use crate::validation::ValidationErrors;
+
+/// How many todos below this one, at any depth, are done.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Progress {
+    completed: i64,
+    total: i64,
+}
+
+/// Ids of `id` and every todo below it, leaving out those in the trash. The
+/// path of each row keeps the walk finite should the tree ever hold a cycle.
+pub const SUBTREE: &str = "WITH RECURSIVE subtree AS ( \
+     SELECT id, 0 AS depth, ARRAY[id] AS path FROM todos WHERE id = $1 AND deleted_at IS NULL \
+     UNION ALL \
+     SELECT t.id, subtree.depth + 1, subtree.path || t.id \
+     FROM todos t JOIN subtree ON t.parent_id = subtree.id \
+     WHERE t.deleted_at IS NULL AND t.id <> ALL (subtree.path))";
+
+/// A todo with its subtasks nested below it.
+#[derive(Debug, Serialize)]
+pub struct TodoTree {
+    #[serde(flatten)]
+    todo: Todo,
+    children: Vec<TodoTree>,
+}
+
+/// Load the todo `id` with its whole subtree.
+pub async fn fetch_tree(
+    conn: &mut PgConnection,
+    user: CurrentUser,
+    id: i32,
+) -> Result<TodoTree, sqlx::Error> {
+    let mut todos: Vec<Todo> = sqlx::query_as(&format!(
+        "{SUBTREE} SELECT {TODO_COLUMNS} FROM todos JOIN subtree USING (id) \
+         WHERE list_id IN (SELECT id FROM lists_with_role($2, 'viewer')) ORDER BY depth, id"
+    ))
+    .bind(id)
+    .bind(user.id)
+    .fetch_all(conn)
+    .await?;
+
+    // Rows come breadth first, so walking them backwards builds every
+    // subtask before its parent.
+    let mut children: HashMap<i32, Vec<TodoTree>> = HashMap::new();
+    while let Some(todo) = todos.pop() {
+        let mut below = children.remove(&todo.id).unwrap_or_default();
+        below.reverse();
+        let node = TodoTree {
+            todo,
+            children: below,
+        };
+        match node.todo.parent_id {
+            Some(parent_id) if node.todo.id != id => {
+                children.entry(parent_id).or_default().push(node)
+            }
+            _ => return Ok(node),
+        }
+    }
+    Err(sqlx::Error::RowNotFound)
+}
+
+/// Mark every todo below `id` as completed.
+pub async fn complete_subtree(conn: &mut PgConnection, id: i32) -> Result<(), sqlx::Error> {
+    sqlx::query(&format!(
+        "{SUBTREE} UPDATE todos SET completed = true \
+         WHERE id IN (SELECT id FROM subtree WHERE depth > 0) AND NOT completed"
+    ))
+    .bind(id)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Move the children of `id` up to its own parent.
+pub async fn reparent_children(conn: &mut PgConnection, id: i32) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE todos
+        SET parent_id = (SELECT parent_id FROM todos WHERE id = $1)
+        WHERE parent_id = $1 AND deleted_at IS NULL
+        "#,
+        id
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Where a todo ends up in the lists and the tree.
+#[derive(Debug, Clone, Copy)]
+pub struct Placement {
+    pub list_id: i32,
+    pub parent_id: Option<i32>,
+}
+
+/// Work out the placement of the todo `id`, or of a new todo when `id` is
+/// `None`, from the requested list and parent. A subtask always follows its
+/// parent's list; the list it ends up in must not be archived.
+pub async fn place(
+    conn: &mut PgConnection,
+    user: CurrentUser,
+    id: Option<i32>,
+    list_id: Option<i32>,
+    parent_id: Patch<i32>,
+) -> Result<Placement, ApiError> {
+    let current = match id {
+        Some(id) => Some(
+            sqlx::query!(
+                r#"
+                SELECT t.list_id, t.parent_id, r.role AS "role?: Role"
+                FROM todos t LEFT JOIN lists_with_role($2, 'viewer') r ON r.id = t.list_id
+                WHERE t.id = $1 AND t.deleted_at IS NULL
+                "#,
+                id,
+                user.id
+            )
+            .fetch_one(&mut *conn)
+            .await?,
+        ),
+        None => None,
+    };
+    if let Some(current) = &current {
+        current
+            .role
+            .ok_or(ApiError::NotFound)?
+            .require(Role::Editor)?;
+    }
+    let explicit_parent = matches!(parent_id, Patch::Value(_));
+    let mut parent_id = match parent_id {
+        Patch::Absent => current.as_ref().and_then(|current| current.parent_id),
+        Patch::Null => None,
+        Patch::Value(parent_id) => Some(parent_id),
+    };
+
+    let list_id = match parent_id {
+        Some(parent) => {
+            if let (true, Some(id)) = (explicit_parent, id) {
+                check_not_below(conn, id, parent).await?;
+            }
+            let parent_list = sqlx::query_scalar!(
+                r#"
+                SELECT list_id FROM todos
+                WHERE id = $1 AND deleted_at IS NULL
+                    AND list_id IN (SELECT id FROM lists_with_role($2, 'viewer'))
+                "#,
+                parent,
+                user.id
+            )
+            .fetch_optional(&mut *conn)
+            .await?
+            .ok_or_else(|| invalid("parent_id", "not_found", "does not exist"))?;
+            match list_id {
+                Some(list_id) if list_id != parent_list && explicit_parent => {
+                    return Err(invalid(
+                        "list_id",
+                        "list_mismatch",
+                        "must be the list of the parent todo",
+                    ));
+                }
+                Some(list_id) if list_id != parent_list => {
+                    // Moving a subtask on its own detaches it.
+                    parent_id = None;
+                    Some(list_id)
+                }
+                _ => Some(parent_list),
+            }
+        }
+        None => list_id.or(current.as_ref().map(|current| current.list_id)),
+    };
+
+    let list_id = match (list_id, &current) {
+        (Some(list_id), Some(current)) if list_id == current.list_id => list_id,
+        (list_id, current) => {
+            let list_id = lists::resolve(conn, user, list_id).await?;
+            if let Some(current) = current {
+                check_same_owner(conn, current.list_id, list_id).await?;
+            }
+            list_id
+        }
+    };
+    Ok(Placement { list_id, parent_id })
+}
+
+/// A todo cannot become a subtask of itself or of one of its subtasks.
+///
+/// Walks up from the new parent, locking the todo and every ancestor on the
+/// way, so that a concurrent move cannot close a cycle behind the check.
+async fn check_not_below(conn: &mut PgConnection, id: i32, parent_id: i32) -> Result<(), ApiError> {
+    sqlx::query!("SELECT id FROM todos WHERE id = $1 FOR UPDATE", id)
+        .fetch_one(&mut *conn)
+        .await?;
+    let mut seen = HashSet::new();
+    let mut ancestor = Some(parent_id);
+    while let Some(current) = ancestor.filter(|&current| seen.insert(current)) {
+        if current == id {
+            return Err(invalid(
+                "parent_id",
+                "cycle",
+                "must not be the todo itself or one of its subtasks",
+            ));
+        }
+        ancestor = sqlx::query_scalar!(
+            "SELECT parent_id FROM todos WHERE id = $1 FOR UPDATE",
+            current
+        )
+        .fetch_optional(&mut *conn)
+        .await?
+        .flatten();
+    }
+    Ok(())
+}
+
+/// Todos belong to the owner of their list, and only move between lists of
+/// the same owner.
+async fn check_same_owner(conn: &mut PgConnection, from: i32, to: i32) -> Result<(), ApiError> {
+    let same = sqlx::query_scalar!(
+        r#"
+        SELECT (SELECT owner_id FROM lists WHERE id = $1)
+            = (SELECT owner_id FROM lists WHERE id = $2) AS "same!"
+        "#,
+        from,
+        to
+    )
+    .fetch_one(conn)
+    .await?;
+    if !same {
+        return Err(invalid(
+            "list_id",
+            "other_owner",
+            "must have the same owner as the current list",
+        ));
+    }
+    Ok(())
+}
+
+fn invalid(field: &str, code: &'static str, message: &str) -> ApiError {
+    let mut errors = ValidationErrors::default();
+    errors.add(field, code, message);
+    ApiError::Validation(errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use sqlx::{Acquire, PgPool};
+
+    use super::*;
+
+    /// Two top level todos in a fresh list.
+    async fn seed(pool: &PgPool) -> (i32, i32) {
+        let ids: Vec<i32> = sqlx::query_scalar(
+            r#"
+            WITH owner AS (
+                INSERT INTO users (email, password_hash) VALUES ('ann@example.com', '')
+                RETURNING id
+            ), inbox AS (
+                INSERT INTO lists (name, inbox, owner_id) SELECT 'Inbox', true, id FROM owner
+                RETURNING id, owner_id
+            )
+            INSERT INTO todos (description, list_id, owner_id)
+            SELECT d, inbox.id, inbox.owner_id FROM inbox, unnest(ARRAY['a', 'b']) AS d
+            RETURNING id
+            "#,
+        )
+        .fetch_all(pool)
+        .await
+        .unwrap();
+        (ids[0], ids[1])
+    }
+
+    async fn set_parent(conn: &mut PgConnection, id: i32, parent_id: i32) -> Result<(), ApiError> {
+        check_not_below(conn, id, parent_id).await?;
+        sqlx::query("UPDATE todos SET parent_id = $2 WHERE id = $1")
+            .bind(id)
+            .bind(parent_id)
+            .execute(conn)
+            .await?;
+        Ok(())
+    }
+
+    #[sqlx::test(migrator = "crate::migrate::MIGRATOR")]
+    async fn concurrent_moves_cannot_close_a_cycle(pool: PgPool) {
+        let (a, b) = seed(&pool).await;
+        let mut first = pool.begin().await.unwrap();
+        set_parent(&mut first, a, b).await.unwrap();
+
+        // The second move waits for the locks of the first.
+        let second = tokio::spawn({
+            let pool = pool.clone();
+            async move {
+                let mut second = pool.begin().await.unwrap();
+                let moved = set_parent(&mut second, b, a).await;
+                second.commit().await.unwrap();
+                moved
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!second.is_finished());
+        first.commit().await.unwrap();
+
+        assert!(matches!(
+            second.await.unwrap(),
+            Err(ApiError::Validation(_))
+        ));
+    }
+
+    #[sqlx::test(migrator = "crate::migrate::MIGRATOR")]
+    async fn the_database_rejects_cycles(pool: PgPool) {
+        let (a, b) = seed(&pool).await;
+        let mut conn = pool.acquire().await.unwrap();
+        sqlx::query("UPDATE todos SET parent_id = $2 WHERE id = $1")
+            .bind(a)
+            .bind(b)
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+        let closed = sqlx::query("UPDATE todos SET parent_id = $2 WHERE id = $1")
+            .bind(b)
+            .bind(a)
+            .execute(&mut *conn)
+            .await
+            .unwrap_err();
+        assert!(matches!(ApiError::from(closed), ApiError::Unprocessable(_)));
+    }
+
+    #[sqlx::test(migrator = "crate::migrate::MIGRATOR")]
+    async fn reads_end_even_with_a_cycle(pool: PgPool) {
+        let (a, b) = seed(&pool).await;
+        let mut conn = pool.acquire().await.unwrap();
+        let mut tx = conn.begin().await.unwrap();
+        sqlx::query("ALTER TABLE todos DISABLE TRIGGER todos_check_cycle")
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+        sqlx::query("UPDATE todos SET parent_id = CASE id WHEN $1 THEN $2 ELSE $1 END")
+            .bind(a)
+            .bind(b)
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+
+        let below: Vec<i32> = sqlx::query_scalar(&format!("{SUBTREE} SELECT id FROM subtree"))
+            .bind(a)
+            .fetch_all(&mut *tx)
+            .await
+            .unwrap();
+        assert_eq!(below, [a, b]);
+        let todo: Todo = sqlx::query_as(&format!("SELECT {TODO_COLUMNS} FROM todos WHERE id = $1"))
+            .bind(a)
+            .fetch_one(&mut *tx)
+            .await
+            .unwrap();
+        assert_eq!(todo.id, a);
+    }
+}
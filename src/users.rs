@@ -0,0 +1,73 @@
+//! Managing user accounts from the command line.
+//!
+//! Besides registering over HTTP, users can be created with `rust-todo user
+//! create`. That is the way to create the first user of a deployment that
+//! predates accounts: migration 0010 hands the existing todos to the first
+//! user and refuses to run until there is one, so the server cannot start.
+
+use std::io::BufRead;
+
+use clap::Subcommand;
+use sqlx::PgPool;
+
+use crate::auth::{self, Credentials};
+use crate::migrate;
+use crate::validation::{Validate, ValidationErrors};
+
+/// The migration that gives lists their owners, and users their inboxes.
+const OWNERS_MIGRATION: i64 = 10;
+
+/// The migration that creates the users table.
+const USERS_MIGRATION: i64 = 9;
+
+#[derive(Debug, Subcommand)]
+pub enum UserCommand {
+    /// Create a user, reading the password from the first line of standard
+    /// input.
+    Create {
+        #[arg(long)]
+        email: String,
+    },
+}
+
+pub async fn run(pool: &PgPool, command: UserCommand) -> Result<(), String> {
+    match command {
+        UserCommand::Create { email } => {
+            let mut password = String::new();
+            std::io::stdin()
+                .lock()
+                .read_line(&mut password)
+                .map_err(|err| format!("failed to read the password: {err}"))?;
+            let password = password.trim_end_matches(['\r', '\n']).to_string();
+
+            let mut input = Credentials { email, password };
+            let mut errors = ValidationErrors::default();
+            input.validate(&mut errors);
+            if !errors.is_empty() {
+                return Err(format!("invalid user:\n{errors}"));
+            }
+
+            let applied = migrate::applied_versions(pool)
+                .await
+                .map_err(|err| format!("failed to read the applied migrations: {err}"))?;
+            if !applied.contains(&USERS_MIGRATION) {
+                return Err(
+                    "the users table does not exist yet, run `rust-todo migrate up` \
+                     first"
+                        .to_string(),
+                );
+            }
+            // Until lists have owners there are no inboxes to create; the
+            // migration gives the first user the existing one.
+            let inbox = applied.contains(&OWNERS_MIGRATION);
+            let user = auth::create_user(pool, input, inbox)
+                .await
+                .map_err(|err| format!("failed to create the user: {err}"))?;
+            println!("Created user {} <{}>.", user.id, user.email);
+            if !inbox {
+                println!("Run `rust-todo migrate up` to hand the existing todos to them.");
+            }
+        }
+    }
+    Ok(())
+}
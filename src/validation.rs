@@ -0,0 +1,512 @@
+//! Request validation.
+//!
+//! Bodies are extracted with [`ValidJson`] and query strings with
+//! [`ValidQuery`]; both deserialize the input, normalize it and run
+//! [`Validate`]. Every failing field is collected so
+//! the client gets the complete list in a single 422 response.
+
+use std::fmt;
+
+use axum::async_trait;
+use axum::extract::{FromRequest, FromRequestParts, Request};
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use axum::Json;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Value};
+use serde_path_to_error::Segment;
+
+use crate::error::ApiError;
+use crate::patch::Patch;
+
+pub trait Validate {
+    /// Normalize `self` in place and record every problem in `errors`.
+    fn validate(&mut self, errors: &mut ValidationErrors);
+}
+
+/// A single failing field, `code` is stable and meant for machines.
+#[derive(Debug, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub code: &'static str,
+    pub message: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(transparent)]
+pub struct ValidationErrors(Vec<FieldError>);
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, error) in self.0.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "  - {} {}", error.field, error.message)?;
+        }
+        Ok(())
+    }
+}
+
+impl ValidationErrors {
+    pub fn add(&mut self, field: &str, code: &'static str, message: impl Into<String>) {
+        self.0.push(FieldError {
+            field: field.to_string(),
+            code,
+            message: message.into(),
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Take over the errors of a nested object found at `path`.
+    pub fn nest(&mut self, path: &str, errors: ValidationErrors) {
+        self.0.extend(errors.0.into_iter().map(|error| FieldError {
+            field: format!("{path}.{}", error.field),
+            ..error
+        }));
+    }
+}
+
+/// Bounds applied to free-form text fields.
+pub struct TextRules {
+    pub min_len: usize,
+    pub max_len: usize,
+    pub allow_newlines: bool,
+}
+
+/// Trim `value` and check it against `rules`. Lengths count characters, not
+/// bytes.
+pub fn text(errors: &mut ValidationErrors, field: &str, value: &mut String, rules: &TextRules) {
+    let trimmed = value.trim();
+    if trimmed.len() != value.len() {
+        *value = trimmed.to_string();
+    }
+
+    let len = value.chars().count();
+    if len == 0 && rules.min_len > 0 {
+        errors.add(field, "blank", "must not be blank");
+    } else if len < rules.min_len {
+        errors.add(
+            field,
+            "too_short",
+            format!("must be at least {} characters", rules.min_len),
+        );
+    }
+    if len > rules.max_len {
+        errors.add(
+            field,
+            "too_long",
+            format!("must be at most {} characters", rules.max_len),
+        );
+    }
+
+    let allowed = |c: char| rules.allow_newlines && (c == '\n' || c == '\t');
+    if value.chars().any(|c| c.is_control() && !allowed(c)) {
+        errors.add(
+            field,
+            "control_characters",
+            "must not contain control characters",
+        );
+    }
+}
+
+/// Check that a `(low, high)` pair of optional bounds is in order.
+pub fn ordered<T: PartialOrd>(
+    errors: &mut ValidationErrors,
+    (low_field, low): (&str, Option<T>),
+    (high_field, high): (&str, Option<T>),
+) {
+    if let (Some(low), Some(high)) = (low, high) {
+        if low >= high {
+            errors.add(
+                low_field,
+                "invalid_range",
+                format!("must be earlier than {high_field}"),
+            );
+        }
+    }
+}
+
+/// Validate a merge-patch member that must not be cleared.
+pub fn required<T>(
+    errors: &mut ValidationErrors,
+    field: &str,
+    value: &mut Patch<T>,
+    check: impl FnOnce(&mut ValidationErrors, &mut T),
+) {
+    match value {
+        Patch::Absent => {}
+        Patch::Null => errors.add(field, "not_nullable", "cannot be null"),
+        Patch::Value(value) => check(errors, value),
+    }
+}
+
+/// A JSON body that has been deserialized and validated.
+pub struct ValidJson<T>(pub T);
+
+#[async_trait]
+impl<S, T> FromRequest<S> for ValidJson<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned + Validate,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        // Parse first and deserialize separately, so that a field that is
+        // missing or of the wrong type is reported like any other, together
+        // with every other failing field.
+        let Json(json) = Json::<Value>::from_request(req, state).await?;
+        let mut errors = ValidationErrors::default();
+        let (value, reported) = deserialize::<T>(json, &mut errors)?;
+        let Some(mut value) = value else {
+            return Err(ApiError::Validation(errors));
+        };
+
+        // Members already reported hold stand-ins, which are not checked.
+        let mut checked = ValidationErrors::default();
+        value.validate(&mut checked);
+        errors.0.extend(
+            checked
+                .0
+                .into_iter()
+                .filter(|error| !reported.iter().any(|path| within(&error.field, path))),
+        );
+        if errors.is_empty() {
+            Ok(ValidJson(value))
+        } else {
+            Err(ApiError::Validation(errors))
+        }
+    }
+}
+
+/// How many failing members of a body [`deserialize`] steps past before it
+/// stops looking for more.
+const MAX_DATA_ERRORS: usize = 50;
+
+type DataError = serde_path_to_error::Error<serde_json::Error>;
+
+/// Deserialize `json`, adding a field error for every member that is missing
+/// or does not fit rather than stopping at the first. Each failing member is
+/// left out or replaced by a stand-in, and the rest deserialized again.
+/// Returns the value, unless some member had no stand-in, together with the
+/// paths that were reported.
+fn deserialize<T: DeserializeOwned>(
+    mut json: Value,
+    errors: &mut ValidationErrors,
+) -> Result<(Option<T>, Vec<String>), ApiError> {
+    let mut reported: Vec<String> = Vec::new();
+    for _ in 0..MAX_DATA_ERRORS {
+        let err = match serde_path_to_error::deserialize(json.clone()) {
+            Ok(value) => return Ok((Some(value), reported)),
+            Err(err) => err,
+        };
+        let segments = error_segments(&err);
+        if segments.is_empty() {
+            return Err(ApiError::Rejected(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "The request body must be a JSON object.".into(),
+            ));
+        }
+        let path = path(&segments);
+        if !reported.contains(&path) {
+            let message = err.inner().to_string();
+            match missing_field(&message) {
+                Some(_) => errors.add(&path, "required", "is required"),
+                None => errors.add(&path, data_error_code(&message), message),
+            }
+            reported.push(path.clone());
+        }
+        if !stand_in::<T>(&mut json, &segments, &path) {
+            break;
+        }
+    }
+    Ok((None, reported))
+}
+
+/// Put something at `segments` that `T` deserializes past: leave the member
+/// out, or try a value of each JSON type and the first variant an enum
+/// expects. Returns whether anything worked.
+fn stand_in<T: DeserializeOwned>(json: &mut Value, segments: &[Segment], path: &str) -> bool {
+    let Some((last, parent)) = segments.split_last() else {
+        return false;
+    };
+    let mut candidates = vec![
+        None,
+        Some(Value::Null),
+        Some(json!("")),
+        Some(json!(0)),
+        Some(json!(false)),
+        Some(json!([])),
+        Some(json!({})),
+    ];
+    let mut tried = 0;
+    while let Some(candidate) = candidates.get(tried).cloned() {
+        tried += 1;
+        let Some(parent) = locate(json, parent) else {
+            return false;
+        };
+        match (last, parent, candidate) {
+            (Segment::Map { key }, Value::Object(object), None) => {
+                object.remove(key);
+            }
+            (Segment::Map { key }, Value::Object(object), Some(candidate)) => {
+                object.insert(key.clone(), candidate);
+            }
+            (Segment::Seq { .. }, Value::Array(_), None) => continue,
+            (Segment::Seq { index }, Value::Array(items), Some(candidate))
+                if *index < items.len() =>
+            {
+                items[*index] = candidate;
+            }
+            _ => return false,
+        }
+        let Err(err) = serde_path_to_error::deserialize::<_, T>(json.clone()) else {
+            return true;
+        };
+        if !within(&self::path(&error_segments(&err)), path) {
+            return true;
+        }
+        if let Some(variant) = expected_variant(&err.inner().to_string()) {
+            let variant = Some(json!(variant));
+            if !candidates.contains(&variant) {
+                candidates.push(variant);
+            }
+        }
+    }
+    false
+}
+
+/// The path of the member an error is about, which for a missing field is
+/// the field rather than the object it is missing from.
+fn error_segments(err: &DataError) -> Vec<Segment> {
+    let mut segments: Vec<Segment> = err.path().iter().cloned().collect();
+    if let Some(field) = missing_field(&err.inner().to_string()) {
+        segments.push(Segment::Map {
+            key: field.to_string(),
+        });
+    }
+    segments
+}
+
+fn missing_field(message: &str) -> Option<&str> {
+    message
+        .strip_prefix("missing field `")
+        .and_then(|rest| rest.strip_suffix('`'))
+}
+
+/// The first variant in an `unknown variant` message.
+fn expected_variant(message: &str) -> Option<&str> {
+    let (_, expected) = message
+        .split_once("expected one of `")
+        .or_else(|| message.split_once("expected `"))?;
+    expected.split('`').next()
+}
+
+/// A stable code for each kind of problem serde reports.
+fn data_error_code(message: &str) -> &'static str {
+    if message.starts_with("invalid type") {
+        "invalid_type"
+    } else if message.starts_with("unknown variant") || message.starts_with("invalid value") {
+        "invalid_value"
+    } else if message.starts_with("invalid length") {
+        "invalid_length"
+    } else if message.starts_with("unknown field") {
+        "unknown_field"
+    } else {
+        "invalid"
+    }
+}
+
+/// The member of `json` at `segments`.
+fn locate<'a>(json: &'a mut Value, segments: &[Segment]) -> Option<&'a mut Value> {
+    segments
+        .iter()
+        .try_fold(json, |value, segment| match segment {
+            Segment::Seq { index } => value.get_mut(*index),
+            Segment::Map { key } => value.get_mut(key.as_str()),
+            // Internally tagged enums have no member for their variant.
+            Segment::Enum { variant } if value.get(variant.as_str()).is_some() => {
+                value.get_mut(variant.as_str())
+            }
+            Segment::Enum { .. } => Some(value),
+            Segment::Unknown => None,
+        })
+}
+
+/// Spell a path the way field errors name it, e.g. `operations[0].todo`.
+fn path(segments: &[Segment]) -> String {
+    let mut path = String::new();
+    for segment in segments {
+        match segment {
+            Segment::Seq { index } => path.push_str(&format!("[{index}]")),
+            Segment::Map { key } | Segment::Enum { variant: key } => {
+                if !path.is_empty() {
+                    path.push('.');
+                }
+                path.push_str(key);
+            }
+            Segment::Unknown => path.push_str(".?"),
+        }
+    }
+    path
+}
+
+/// Whether `field` is the member at `path` or inside it.
+fn within(field: &str, path: &str) -> bool {
+    field
+        .strip_prefix(path)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with(['.', '[']))
+}
+
+/// Query parameters that have been deserialized and validated. Repeated
+/// parameters such as `?tag=a&tag=b` deserialize into a `Vec`.
+pub struct ValidQuery<T>(pub T);
+
+#[async_trait]
+impl<S, T> FromRequestParts<S> for ValidQuery<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned + Validate,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let query = parts.uri.query().unwrap_or_default();
+        let mut value: T = serde_html_form::from_str(query).map_err(|err| {
+            ApiError::Rejected(
+                StatusCode::BAD_REQUEST,
+                format!("Failed to deserialize query string: {err}"),
+            )
+        })?;
+
+        let mut errors = ValidationErrors::default();
+        value.validate(&mut errors);
+        if errors.is_empty() {
+            Ok(ValidQuery(value))
+        } else {
+            Err(ApiError::Validation(errors))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    enum Level {
+        Low,
+        High,
+    }
+
+    /// Only some fields are validated, the others are there to fail
+    /// deserializing.
+    #[derive(Debug, Deserialize)]
+    #[allow(dead_code)]
+    struct Item {
+        name: String,
+        level: Level,
+        done: bool,
+        #[serde(default)]
+        notes: Vec<String>,
+        parent: Option<i32>,
+    }
+
+    const NAME: TextRules = TextRules {
+        min_len: 1,
+        max_len: 10,
+        allow_newlines: false,
+    };
+
+    impl Validate for Item {
+        fn validate(&mut self, errors: &mut ValidationErrors) {
+            text(errors, "name", &mut self.name, &NAME);
+            for (i, note) in self.notes.iter_mut().enumerate() {
+                text(errors, &format!("notes[{i}]"), note, &NAME);
+            }
+        }
+    }
+
+    async fn errors(body: &str) -> Vec<(String, &'static str)> {
+        let request = Request::post("/")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        match ValidJson::<Item>::from_request(request, &()).await {
+            Err(ApiError::Validation(errors)) => errors
+                .0
+                .into_iter()
+                .map(|error| (error.field, error.code))
+                .collect(),
+            Err(err) => panic!("unexpected rejection: {err}"),
+            Ok(_) => panic!("the body was accepted"),
+        }
+    }
+
+    fn fields(expected: &[(&str, &'static str)]) -> Vec<(String, &'static str)> {
+        expected
+            .iter()
+            .map(|(field, code)| (field.to_string(), *code))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn reports_mistyped_fields_with_failed_validation() {
+        assert_eq!(
+            errors(r#"{"name": "  ", "level": "bogus", "done": true}"#).await,
+            fields(&[("level", "invalid_value"), ("name", "blank")])
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_every_missing_field() {
+        assert_eq!(
+            errors(r#"{"done": true}"#).await,
+            fields(&[("name", "required"), ("level", "required")])
+        );
+        assert_eq!(
+            errors("{}").await,
+            fields(&[
+                ("name", "required"),
+                ("level", "required"),
+                ("done", "required")
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_mistyped_and_missing_fields_together() {
+        assert_eq!(
+            errors(r#"{"name": 5, "done": "yes", "parent": "x", "notes": ["ok", 3, ""]}"#).await,
+            fields(&[
+                ("done", "invalid_type"),
+                ("name", "invalid_type"),
+                ("notes[1]", "invalid_type"),
+                ("parent", "invalid_type"),
+                ("level", "required"),
+                ("notes[2]", "blank"),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_a_body_that_is_not_an_object() {
+        let request = Request::post("/")
+            .header("content-type", "application/json")
+            .body(Body::from("[1]"))
+            .unwrap();
+        assert!(matches!(
+            ValidJson::<Item>::from_request(request, &()).await,
+            Err(ApiError::Rejected(StatusCode::UNPROCESSABLE_ENTITY, _))
+        ));
+    }
+}
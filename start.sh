@@ -1 +1 @@
-RUST_LOG=tower_http=debug,axumapp11=debug cargo run
\ No newline at end of file
+RUST_LOG=tower_http=debug,rust_todo=debug cargo run